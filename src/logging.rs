@@ -0,0 +1,187 @@
+use anyhow::{anyhow, Result};
+use env_filter::Filter;
+use env_logger::{fmt::Formatter, Env};
+use log::{
+    kv::{self, Key, Value, VisitSource},
+    LevelFilter, Log, Metadata, Record,
+};
+use serde_json::{Map, Value as JsonValue};
+use std::{
+    env,
+    io::Write,
+    sync::{LazyLock, RwLock},
+};
+
+/// What gets logged, in `RUST_LOG` syntax. Replaced by [`set_filter`].
+static FILTER: LazyLock<RwLock<LogFilter>> = LazyLock::new(|| {
+    RwLock::new(LogFilter {
+        spec: String::new(),
+        filter: env_filter::Builder::new().build(),
+    })
+});
+
+struct LogFilter {
+    spec: String,
+    filter: Filter,
+}
+
+/// Sets up logging. `RUST_LOG` selects what gets logged and
+/// `WEBHOOK_LOG_FORMAT` (`text` or `json`) how it's written.
+pub fn init() -> Result<()> {
+    let spec = env::var("RUST_LOG").unwrap_or("info".to_string());
+    let mut builder = env_logger::Builder::from_env(Env::new().write_style("RUST_LOG_STYLE"));
+    // Everything reaching the inner logger has already passed FILTER.
+    builder.filter_level(LevelFilter::Trace);
+
+    match env::var("WEBHOOK_LOG_FORMAT").as_deref() {
+        Err(_) | Ok("text") => {}
+        Ok("json") => {
+            builder.format(format_json);
+        }
+        Ok(format) => {
+            return Err(anyhow!(
+                "Unsupported WEBHOOK_LOG_FORMAT {}, expected text or json",
+                format
+            ))
+        }
+    }
+
+    // Unparseable directives are reported and skipped at startup, as
+    // env_logger does, rather than stopping the webhook.
+    replace_filter(&spec, env_filter::Builder::new().parse(&spec).build());
+    log::set_boxed_logger(Box::new(FilteredLogger(builder.build())))
+        .map_err(|e| anyhow!("Error setting up logging: {}", e))?;
+    Ok(())
+}
+
+/// Changes what gets logged at runtime. `spec` uses `RUST_LOG` syntax, e.g.
+/// `info,protect_webhook=debug`.
+pub fn set_filter(spec: &str) -> Result<()> {
+    let filter = env_filter::Builder::new()
+        .try_parse(spec)
+        .map_err(|e| anyhow!("invalid log filter {}: {}", spec, e))?
+        .build();
+    replace_filter(spec, filter);
+    Ok(())
+}
+
+/// The filter in effect, in `RUST_LOG` syntax.
+pub fn filter() -> String {
+    FILTER.read().unwrap().spec.clone()
+}
+
+fn replace_filter(spec: &str, filter: Filter) {
+    log::set_max_level(filter.filter());
+    *FILTER.write().unwrap() = LogFilter {
+        spec: spec.to_string(),
+        filter,
+    };
+}
+
+struct FilteredLogger(env_logger::Logger);
+
+impl Log for FilteredLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        FILTER.read().unwrap().filter.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if FILTER.read().unwrap().filter.matches(record) {
+            self.0.log(record);
+        }
+    }
+
+    fn flush(&self) {
+        self.0.flush();
+    }
+}
+
+fn format_json(buf: &mut Formatter, record: &Record) -> std::io::Result<()> {
+    let line = json_line(record, buf.timestamp().to_string());
+    writeln!(buf, "{}", line)
+}
+
+/// One JSON object per record, with the record's key-values as fields.
+fn json_line(record: &Record, timestamp: String) -> JsonValue {
+    let mut line = Map::new();
+    line.insert("timestamp".to_string(), timestamp.into());
+    line.insert("level".to_string(), record.level().as_str().into());
+    line.insert("target".to_string(), record.target().into());
+    line.insert("message".to_string(), record.args().to_string().into());
+
+    // Visiting a map can't fail.
+    let _ = record.key_values().visit(&mut JsonFields(&mut line));
+
+    JsonValue::Object(line)
+}
+
+struct JsonFields<'a>(&'a mut Map<String, JsonValue>);
+
+impl<'kvs> VisitSource<'kvs> for JsonFields<'_> {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), kv::Error> {
+        let value = if let Some(b) = value.to_bool() {
+            b.into()
+        } else if let Some(n) = value.to_u64() {
+            n.into()
+        } else if let Some(n) = value.to_i64() {
+            n.into()
+        } else {
+            value.to_string().into()
+        };
+        self.0.insert(key.to_string(), value);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use log::Level;
+    use serde_json::json;
+
+    #[test]
+    fn test_json_line() {
+        let kvs: &[(&str, &str)] = &[("uid", "abc"), ("decision", "injected")];
+        let line = json_line(
+            &Record::builder()
+                .level(Level::Info)
+                .target("protect_webhook::server::mutate")
+                .args(format_args!("mutating default/test-pod"))
+                .key_values(&kvs)
+                .build(),
+            "2026-01-01T00:00:00Z".to_string(),
+        );
+
+        assert_eq!(
+            line,
+            json!({
+                "timestamp": "2026-01-01T00:00:00Z",
+                "level": "INFO",
+                "target": "protect_webhook::server::mutate",
+                "message": "mutating default/test-pod",
+                "uid": "abc",
+                "decision": "injected",
+            })
+        );
+    }
+
+    #[test]
+    fn test_set_filter() {
+        set_filter("warn,protect_webhook=debug").unwrap();
+        assert_eq!(filter(), "warn,protect_webhook=debug");
+        assert_eq!(log::max_level(), LevelFilter::Debug);
+
+        let metadata = |target| {
+            Metadata::builder()
+                .level(Level::Debug)
+                .target(target)
+                .build()
+        };
+        let logger = FilteredLogger(env_logger::Builder::new().build());
+        assert!(logger.enabled(&metadata("protect_webhook::server")));
+        assert!(!logger.enabled(&metadata("hyper::proto")));
+
+        assert!(set_filter("protect_webhook=loud").is_err());
+        assert_eq!(filter(), "warn,protect_webhook=debug");
+    }
+}
@@ -1,9 +1,10 @@
 use anyhow::Result;
 
+mod logging;
 mod server;
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
+    logging::init()?;
     server::start().await
 }
@@ -0,0 +1,59 @@
+use log::warn;
+use std::{io, time::Duration};
+
+const MIN_DELAY: Duration = Duration::from_millis(10);
+const MAX_DELAY: Duration = Duration::from_secs(1);
+
+/// Spaces out retries after failed accepts, so a persistent error such as
+/// running out of file descriptors doesn't spin the accept loop and flood the
+/// log. The delay doubles with each failure in a row, up to a second.
+#[derive(Debug, Default)]
+pub struct AcceptBackoff {
+    delay: Option<Duration>,
+}
+
+impl AcceptBackoff {
+    pub fn new() -> AcceptBackoff {
+        AcceptBackoff::default()
+    }
+
+    /// Logs `error` and returns how long to wait before accepting again.
+    pub fn failed(&mut self, error: &io::Error) -> Duration {
+        let delay = self
+            .delay
+            .map_or(MIN_DELAY, |delay| (delay * 2).min(MAX_DELAY));
+        warn!(
+            "failed to accept connection: {}, retrying in {}",
+            error,
+            humantime::format_duration(delay)
+        );
+        self.delay = Some(delay);
+        delay
+    }
+
+    /// Starts over after a connection was accepted.
+    pub fn reset(&mut self) {
+        self.delay = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_accept_backoff() {
+        let error = io::Error::from_raw_os_error(24);
+        let mut backoff = AcceptBackoff::new();
+
+        assert_eq!(backoff.failed(&error), MIN_DELAY);
+        assert_eq!(backoff.failed(&error), MIN_DELAY * 2);
+        for _ in 0..10 {
+            backoff.failed(&error);
+        }
+        assert_eq!(backoff.failed(&error), MAX_DELAY);
+
+        backoff.reset();
+        assert_eq!(backoff.failed(&error), MIN_DELAY);
+    }
+}
@@ -0,0 +1,432 @@
+use anyhow::{anyhow, Result};
+use bytes::Bytes;
+use log::{debug, error, info, warn};
+use ring::constant_time;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::sync::Arc;
+use warp::{
+    http::StatusCode,
+    reply::{Json, WithStatus},
+    Filter, Rejection,
+};
+
+use super::killswitch::KillSwitch;
+use super::policy::SharedPolicy;
+use super::readyz::{self, Readiness};
+use super::recent::RecentDecisions;
+use super::{metrics, mutate, tls, version};
+use crate::logging;
+
+/// Runtime configuration endpoints on the admin listener. Every request needs
+/// `Authorization: Bearer <token>`, and the endpoints aren't served at all
+/// without a token.
+pub struct Admin {
+    token: Option<String>,
+    policy: Arc<SharedPolicy>,
+    /// `None` when `/mutate` is only served without TLS.
+    cert: Option<Arc<tls::ServingCert>>,
+    readiness: Arc<Readiness>,
+    recent: Arc<RecentDecisions>,
+    kill_switch: Arc<KillSwitch>,
+}
+
+#[derive(Debug)]
+struct Unauthorized;
+
+impl warp::reject::Reject for Unauthorized {}
+
+#[derive(Deserialize)]
+struct DecisionsQuery {
+    namespace: Option<String>,
+    name: Option<String>,
+}
+
+impl Admin {
+    pub fn new(
+        token: Option<String>,
+        policy: Arc<SharedPolicy>,
+        cert: Option<Arc<tls::ServingCert>>,
+        readiness: Arc<Readiness>,
+        recent: Arc<RecentDecisions>,
+        kill_switch: Arc<KillSwitch>,
+    ) -> Admin {
+        Admin {
+            token,
+            policy,
+            cert,
+            readiness,
+            recent,
+            kill_switch,
+        }
+    }
+
+    fn config(&self) -> Value {
+        let policy = self.policy.current();
+        json!({
+            "policy": *policy,
+            "hash": policy.hash(),
+            "file": self.policy.path(),
+            "logFilter": logging::filter(),
+            "killSwitch": self.kill_switch(),
+        })
+    }
+
+    fn kill_switch(&self) -> Value {
+        let source = self.kill_switch.engaged();
+        json!({ "engaged": source.is_some(), "source": source })
+    }
+
+    /// Reloads the policy and the serving certificate, if there is one.
+    /// Neither changes unless both load and the new policy passes the
+    /// self-test.
+    fn reload(&self) -> Result<Value> {
+        let policy = self.policy.read()?;
+        mutate::self_test(&policy)
+            .map_err(|e| anyhow!("Self-test failed with the reloaded policy: {}", e))?;
+        let key_pair = self
+            .cert
+            .as_ref()
+            .map(|cert| cert.load_key_pair())
+            .transpose()?;
+        let expiry = key_pair.as_ref().map(|key_pair| key_pair.expiry);
+
+        if let (Some(cert), Some(key_pair)) = (&self.cert, key_pair) {
+            let expiry = key_pair.expiry;
+            cert.replace(key_pair)?;
+            metrics::set_tls_certificate_expiry(expiry);
+            self.readiness.set_certificate_expiry(expiry);
+        }
+
+        let hash = policy.hash();
+        self.policy.replace(policy);
+        version::publish(&hash);
+        self.readiness
+            .set(readyz::SELF_TEST, Ok("passed".to_string()));
+        match &self.cert {
+            Some(_) => info!("reloaded policy {} and serving certificate", hash),
+            None => info!("reloaded policy {}", hash),
+        }
+
+        Ok(json!({
+            "hash": hash,
+            "certificateExpiry": expiry,
+        }))
+    }
+
+    fn authorize(&self, header: Option<&str>) -> Result<(), Rejection> {
+        let Some(token) = &self.token else {
+            return Err(warp::reject::not_found());
+        };
+
+        let given = header.and_then(|header| header.strip_prefix("Bearer "));
+        match given {
+            Some(given)
+                if constant_time::verify_slices_are_equal(given.as_bytes(), token.as_bytes())
+                    .is_ok() =>
+            {
+                Ok(())
+            }
+            _ => {
+                warn!("rejected admin request without a valid token");
+                Err(warp::reject::custom(Unauthorized))
+            }
+        }
+    }
+}
+
+pub fn handler(
+    admin: Arc<Admin>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    // The token is checked before the method, so a disabled API is a 404
+    // whatever the method.
+    let config = warp::path!("config")
+        .and(authorized(admin.clone()))
+        .and(warp::get())
+        .map(|admin: Arc<Admin>| {
+            debug!("GET /config");
+            reply(StatusCode::OK, admin.config())
+        });
+
+    let reload = warp::path!("reload")
+        .and(authorized(admin.clone()))
+        .and(warp::post())
+        .then(|admin: Arc<Admin>| async move {
+            info!("POST /reload");
+            // Reading the files and the test handshake block, so they run on
+            // the blocking pool rather than stalling admissions.
+            let reloaded = tokio::task::spawn_blocking(move || admin.reload())
+                .await
+                .unwrap_or_else(|e| Err(anyhow!("reload panicked: {}", e)));
+            match reloaded {
+                Ok(reloaded) => reply(StatusCode::OK, reloaded),
+                Err(e) => {
+                    error!("reload failed, keeping the current configuration: {}", e);
+                    reply(
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                        json!({ "error": e.to_string() }),
+                    )
+                }
+            }
+        });
+
+    let decisions = warp::path!("decisions")
+        .and(authorized(admin.clone()))
+        .and(warp::get())
+        .and(warp::query::<DecisionsQuery>())
+        .map(|admin: Arc<Admin>, query: DecisionsQuery| {
+            debug!("GET /decisions");
+            let decisions = admin
+                .recent
+                .find(query.namespace.as_deref(), query.name.as_deref());
+            reply(StatusCode::OK, json!({ "decisions": decisions }))
+        });
+
+    let kill_switch = warp::path!("killswitch")
+        .and(authorized(admin.clone()))
+        .and(warp::put())
+        .and(warp::body::content_length_limit(4096))
+        .and(warp::body::bytes())
+        .map(|admin: Arc<Admin>, body: Bytes| {
+            let state = String::from_utf8_lossy(&body).trim().to_string();
+            warn!("PUT /killswitch {}", state);
+            match state.as_str() {
+                "on" => admin.kill_switch.set(true),
+                "off" => admin.kill_switch.set(false),
+                _ => {
+                    return reply(
+                        StatusCode::BAD_REQUEST,
+                        json!({ "error": format!("expected on or off, got {:?}", state) }),
+                    )
+                }
+            }
+            reply(StatusCode::OK, json!({ "killSwitch": admin.kill_switch() }))
+        });
+
+    let log_level = warp::path!("loglevel")
+        .and(authorized(admin))
+        .and(warp::put())
+        .and(warp::body::content_length_limit(4096))
+        .and(warp::body::bytes())
+        .map(|_: Arc<Admin>, body: Bytes| {
+            let spec = String::from_utf8_lossy(&body).trim().to_string();
+            info!("PUT /loglevel {}", spec);
+            match logging::set_filter(&spec) {
+                Ok(()) => reply(StatusCode::OK, json!({ "logFilter": spec })),
+                Err(e) => reply(StatusCode::BAD_REQUEST, json!({ "error": e.to_string() })),
+            }
+        });
+
+    config
+        .or(reload)
+        .unify()
+        .or(decisions)
+        .unify()
+        .or(kill_switch)
+        .unify()
+        .or(log_level)
+        .unify()
+        .recover(answer_unauthorized)
+}
+
+fn authorized(
+    admin: Arc<Admin>,
+) -> impl Filter<Extract = (Arc<Admin>,), Error = Rejection> + Clone {
+    warp::header::optional::<String>("authorization").and_then(move |header: Option<String>| {
+        let admin = admin.clone();
+        async move {
+            admin.authorize(header.as_deref())?;
+            Ok::<_, Rejection>(admin)
+        }
+    })
+}
+
+fn reply(status: StatusCode, body: Value) -> WithStatus<Json> {
+    warp::reply::with_status(warp::reply::json(&body), status)
+}
+
+async fn answer_unauthorized(rejection: Rejection) -> Result<impl warp::Reply, Rejection> {
+    if rejection.find::<Unauthorized>().is_none() {
+        return Err(rejection);
+    }
+
+    Ok(warp::reply::with_header(
+        reply(StatusCode::UNAUTHORIZED, json!({ "error": "unauthorized" })),
+        "www-authenticate",
+        "Bearer",
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::audit::AuditEntry;
+    use crate::server::policy::Policy;
+    use crate::server::testing::TempDir;
+    use std::fs;
+    use warp::test::request;
+
+    const CERTS_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/data/certs");
+
+    fn admin(token: Option<&str>, policy_path: Option<String>) -> Arc<Admin> {
+        let crt_path = format!("{}/tls.crt", CERTS_DIR);
+        let key_path = format!("{}/tls.key", CERTS_DIR);
+        let key_pair = tls::load_key_pair(&crt_path, &key_path).unwrap();
+        let policy = match &policy_path {
+            Some(path) => Policy::load(path).unwrap(),
+            None => Policy::default(),
+        };
+
+        Arc::new(Admin::new(
+            token.map(str::to_string),
+            Arc::new(SharedPolicy::new(policy_path, policy)),
+            Some(Arc::new(
+                tls::ServingCert::new(crt_path, key_path, key_pair).unwrap(),
+            )),
+            Arc::new(Readiness::new()),
+            Arc::new(RecentDecisions::new(10)),
+            Arc::new(KillSwitch::new(false, None, Arc::new(Readiness::new()))),
+        ))
+    }
+
+    #[tokio::test]
+    async fn test_authorization() {
+        let filter = handler(admin(Some("s3cret"), None));
+
+        let response = request().path("/config").reply(&filter).await;
+        assert_eq!(response.status(), 401);
+        assert_eq!(response.headers()["www-authenticate"], "Bearer");
+
+        let response = request()
+            .path("/config")
+            .header("authorization", "Bearer guess")
+            .reply(&filter)
+            .await;
+        assert_eq!(response.status(), 401);
+
+        let response = request()
+            .path("/config")
+            .header("authorization", "Bearer s3cret")
+            .reply(&filter)
+            .await;
+        assert_eq!(response.status(), 200);
+        let config: Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(config["hash"], Policy::default().hash());
+        assert_eq!(config["policy"]["runtimeClassName"], "edera");
+
+        // Without a token the endpoints don't exist.
+        let filter = handler(admin(None, None));
+        let response = request().path("/config").reply(&filter).await;
+        assert_eq!(response.status(), 404);
+    }
+
+    #[tokio::test]
+    async fn test_reload() {
+        let dir = TempDir::new("reload");
+        let path = dir.join("policy.json");
+        fs::write(&path, r#"{ "runtimeClassName": "edera" }"#).unwrap();
+
+        let admin = admin(Some("s3cret"), Some(path.display().to_string()));
+        let filter = handler(admin.clone());
+        let reload = || {
+            request()
+                .method("POST")
+                .path("/reload")
+                .header("authorization", "Bearer s3cret")
+                .reply(&filter)
+        };
+
+        fs::write(&path, r#"{ "runtimeClassName": "edera-gpu" }"#).unwrap();
+        let response = reload().await;
+        assert_eq!(response.status(), 200);
+        assert_eq!(admin.policy.current().runtime_class_name, "edera-gpu");
+        let reloaded: Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(reloaded["hash"], admin.policy.current().hash());
+
+        fs::write(&path, r#"{ "runtimeClassName": "#).unwrap();
+        let response = reload().await;
+        assert_eq!(response.status(), 500);
+        assert_eq!(admin.policy.current().runtime_class_name, "edera-gpu");
+    }
+
+    #[tokio::test]
+    async fn test_decisions() {
+        let admin = admin(Some("s3cret"), None);
+        let filter = handler(admin.clone());
+        for (uid, namespace, name) in [("0", "default", "web"), ("1", "team-a", "web")] {
+            admin.recent.record(
+                AuditEntry {
+                    timestamp: AuditEntry::now(),
+                    uid: uid.to_string(),
+                    user: "test".to_string(),
+                    kind: "Pod".to_string(),
+                    namespace: namespace.to_string(),
+                    name: name.to_string(),
+                    operation: "CREATE".to_string(),
+                    decision: "skipped".to_string(),
+                    rule: "not_opted_in".to_string(),
+                    patch: None,
+                },
+                format!("skipping mutation for {}/{}: not opted in", namespace, name),
+            );
+        }
+
+        let response = request()
+            .path("/decisions?namespace=team-a&name=web")
+            .header("authorization", "Bearer s3cret")
+            .reply(&filter)
+            .await;
+        assert_eq!(response.status(), 200);
+        let body: Value = serde_json::from_slice(response.body()).unwrap();
+        let decisions = body["decisions"].as_array().unwrap();
+        assert_eq!(decisions.len(), 1);
+        assert_eq!(decisions[0]["uid"], "1");
+        assert_eq!(decisions[0]["decision"], "skipped");
+        assert_eq!(
+            decisions[0]["message"],
+            "skipping mutation for team-a/web: not opted in"
+        );
+
+        let response = request().path("/decisions").reply(&filter).await;
+        assert_eq!(response.status(), 401);
+    }
+
+    #[tokio::test]
+    async fn test_kill_switch() {
+        let admin = admin(Some("s3cret"), None);
+        let filter = handler(admin.clone());
+        let put = |state: &'static str| {
+            request()
+                .method("PUT")
+                .path("/killswitch")
+                .header("authorization", "Bearer s3cret")
+                .body(state)
+                .reply(&filter)
+        };
+
+        let response = put("on").await;
+        assert_eq!(response.status(), 200);
+        let body: Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(body["killSwitch"]["engaged"], true);
+        assert_eq!(body["killSwitch"]["source"], "the admin API");
+        assert!(admin.kill_switch.engaged().is_some());
+
+        assert_eq!(put("maybe").await.status(), 400);
+        assert!(admin.kill_switch.engaged().is_some());
+
+        assert_eq!(put("off").await.status(), 200);
+        assert_eq!(admin.kill_switch.engaged(), None);
+    }
+
+    #[tokio::test]
+    async fn test_invalid_log_filter() {
+        let response = request()
+            .method("PUT")
+            .path("/loglevel")
+            .header("authorization", "Bearer s3cret")
+            .body("protect_webhook=loud")
+            .reply(&handler(admin(Some("s3cret"), None)))
+            .await;
+        assert_eq!(response.status(), 400);
+    }
+}
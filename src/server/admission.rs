@@ -0,0 +1,293 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct AdmissionReview {
+    pub request: Option<AdmissionRequest>,
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct AdmissionRequest {
+    pub uid: String,
+    pub kind: Option<KindInfo>,
+    pub object: K8sObject,
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub namespace: Option<String>,
+    #[serde(default)]
+    pub operation: Option<String>,
+    #[serde(default)]
+    #[serde(rename = "userInfo")]
+    pub user_info: Option<UserInfo>,
+}
+
+impl AdmissionRequest {
+    /// Workload kinds whose pods are described by `spec.template`.
+    pub fn is_templated(&self) -> bool {
+        matches!(
+            self.kind.as_ref().map(|kind_info| kind_info.kind.as_str()),
+            Some("Deployment" | "ReplicaSet" | "StatefulSet" | "DaemonSet")
+        )
+    }
+
+    pub fn kind_name(&self) -> String {
+        self.kind
+            .as_ref()
+            .map(|kind_info| kind_info.kind.clone())
+            .unwrap_or_default()
+    }
+
+    pub fn object_name(&self) -> String {
+        let metadata = &self.object.metadata;
+        metadata
+            .name
+            .clone()
+            .or_else(|| self.name.clone())
+            .or_else(|| metadata.generate_name.clone())
+            .unwrap_or_else(|| "unknown".to_string())
+    }
+
+    pub fn username(&self) -> String {
+        self.user_info
+            .as_ref()
+            .map(|user_info| user_info.username.clone())
+            .unwrap_or_default()
+    }
+
+    pub fn object_namespace(&self) -> String {
+        self.object
+            .metadata
+            .namespace
+            .clone()
+            .or_else(|| self.namespace.clone())
+            .unwrap_or_default()
+    }
+
+    /// Returns the pod template of templated workload kinds.
+    pub fn pod_template(&self) -> Option<&PodTemplateSpec> {
+        if !self.is_templated() {
+            return None;
+        }
+        self.object.spec.as_ref()?.template.as_ref()
+    }
+
+    /// Looks up an annotation on the object, falling back to the pod template's
+    /// annotations for templated workload kinds.
+    pub fn annotation(&self, key: &str) -> Option<&str> {
+        self.object
+            .metadata
+            .annotations
+            .get(key)
+            .or_else(|| self.pod_template()?.metadata.as_ref()?.annotations.get(key))
+            .map(String::as_str)
+    }
+
+    /// Returns the pod spec being admitted, looking through `spec.template` for
+    /// templated workload kinds.
+    pub fn pod_spec(&self) -> Option<&PodSpec> {
+        if self.is_templated() {
+            self.pod_template()?.spec.as_ref()
+        } else {
+            Some(&self.object.spec.as_ref()?.pod)
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct K8sObject {
+    pub metadata: Metadata,
+    #[serde(default)]
+    pub spec: Option<ObjectSpec>,
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct Metadata {
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    #[serde(rename = "generateName")]
+    pub generate_name: Option<String>,
+    #[serde(default)]
+    pub namespace: Option<String>,
+    #[serde(default)]
+    pub labels: BTreeMap<String, String>,
+    #[serde(default)]
+    pub annotations: BTreeMap<String, String>,
+    #[serde(default)]
+    #[serde(rename = "ownerReferences")]
+    pub owner_references: Vec<OwnerReference>,
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct OwnerReference {
+    pub kind: String,
+    pub name: String,
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct UserInfo {
+    #[serde(default)]
+    pub username: String,
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct KindInfo {
+    pub kind: String,
+}
+
+/// The `spec` of an admitted object. Pods carry their fields inline while
+/// workload kinds nest them under `template`.
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct ObjectSpec {
+    #[serde(flatten)]
+    pub pod: PodSpec,
+    #[serde(default)]
+    pub template: Option<PodTemplateSpec>,
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct PodTemplateSpec {
+    #[serde(default)]
+    pub metadata: Option<Metadata>,
+    #[serde(default)]
+    pub spec: Option<PodSpec>,
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct PodSpec {
+    #[serde(default)]
+    pub containers: Vec<Container>,
+    #[serde(default)]
+    pub init_containers: Vec<Container>,
+    #[serde(default)]
+    pub os: Option<PodOs>,
+    #[serde(default)]
+    pub node_selector: BTreeMap<String, String>,
+    #[serde(default)]
+    pub affinity: Option<Affinity>,
+    #[serde(default)]
+    pub runtime_class_name: Option<String>,
+}
+
+impl PodSpec {
+    pub fn all_containers(&self) -> impl Iterator<Item = &Container> {
+        self.init_containers.iter().chain(self.containers.iter())
+    }
+
+    /// Required node selector terms. The pod can only schedule onto a node
+    /// matching at least one of them.
+    pub fn required_node_selector_terms(&self) -> &[NodeSelectorTerm] {
+        self.affinity
+            .as_ref()
+            .and_then(|affinity| affinity.node_affinity.as_ref())
+            .and_then(|node_affinity| {
+                node_affinity
+                    .required_during_scheduling_ignored_during_execution
+                    .as_ref()
+            })
+            .map(|selector| selector.node_selector_terms.as_slice())
+            .unwrap_or_default()
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct PodOs {
+    pub name: String,
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct Affinity {
+    #[serde(default)]
+    pub node_affinity: Option<NodeAffinity>,
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct NodeAffinity {
+    #[serde(default)]
+    pub required_during_scheduling_ignored_during_execution: Option<NodeSelector>,
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct NodeSelector {
+    #[serde(default)]
+    pub node_selector_terms: Vec<NodeSelectorTerm>,
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct NodeSelectorTerm {
+    #[serde(default)]
+    pub match_expressions: Vec<NodeSelectorRequirement>,
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct NodeSelectorRequirement {
+    pub key: String,
+    pub operator: String,
+    #[serde(default)]
+    pub values: Vec<String>,
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct Container {
+    #[serde(default)]
+    pub resources: Option<ResourceRequirements>,
+}
+
+impl Container {
+    /// Names of all resources requested or limited with a non-zero quantity.
+    pub fn resource_names(&self) -> impl Iterator<Item = &str> {
+        self.resources
+            .iter()
+            .flat_map(|resources| resources.requests.iter().chain(resources.limits.iter()))
+            .filter(|(_, quantity)| !is_zero_quantity(quantity))
+            .map(|(name, _)| name.as_str())
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct ResourceRequirements {
+    #[serde(default)]
+    pub requests: BTreeMap<String, serde_json::Value>,
+    #[serde(default)]
+    pub limits: BTreeMap<String, serde_json::Value>,
+}
+
+fn is_zero_quantity(quantity: &serde_json::Value) -> bool {
+    match quantity {
+        serde_json::Value::String(s) => s.trim() == "0",
+        serde_json::Value::Number(n) => n.as_f64() == Some(0.0),
+        _ => false,
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct AdmissionReviewResponse {
+    pub api_version: String,
+    pub kind: String,
+    pub response: Option<Response>,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Response {
+    pub uid: String,
+    pub allowed: bool,
+    pub patch_type: Option<String>,
+    pub patch: Option<String>,
+    /// Why the request was denied, shown to whoever made it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<Status>,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct Status {
+    pub code: u16,
+    pub message: String,
+}
@@ -0,0 +1,302 @@
+use anyhow::{anyhow, Result};
+use log::error;
+use serde::{Deserialize, Serialize};
+use serde_json::{ser::Formatter, Serializer, Value};
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::mpsc::{self, SyncSender, TrySendError},
+    thread::{self, JoinHandle},
+    time::SystemTime,
+};
+
+use super::metrics;
+
+/// Entries waiting for the writer before new ones are dropped.
+const QUEUE_CAPACITY: usize = 1024;
+
+/// One admission decision, written as a line of JSON.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct AuditEntry {
+    pub timestamp: String,
+    pub uid: String,
+    pub user: String,
+    pub kind: String,
+    pub namespace: String,
+    pub name: String,
+    pub operation: String,
+    pub decision: String,
+    pub rule: String,
+    /// The JSON Patch sent back to the API server, `null` when skipped.
+    pub patch: Option<Value>,
+}
+
+impl AuditEntry {
+    pub fn now() -> String {
+        humantime::format_rfc3339_millis(SystemTime::now()).to_string()
+    }
+}
+
+/// Appends decisions to a JSONL file, rotating it once it grows past
+/// `max_bytes`. Rotated files get numeric suffixes, `.1` being the newest,
+/// and the oldest beyond `max_files` is removed. The file is written on its
+/// own thread, so a slow disk or a rotation never holds up admission.
+pub struct AuditLog {
+    path: PathBuf,
+    /// `None` once dropped, which closes the queue.
+    tx: Option<SyncSender<AuditEntry>>,
+    writer: Option<JoinHandle<()>>,
+}
+
+impl AuditLog {
+    pub fn open(path: impl Into<PathBuf>, max_bytes: u64, max_files: usize) -> Result<AuditLog> {
+        let path = path.into();
+        let mut writer = AuditWriter::open(path.clone(), max_bytes, max_files)?;
+        let (tx, rx) = mpsc::sync_channel::<AuditEntry>(QUEUE_CAPACITY);
+        let writer = thread::Builder::new()
+            .name("audit-log".to_string())
+            .spawn(move || {
+                for entry in rx {
+                    writer.record(&entry);
+                }
+            })
+            .map_err(|e| anyhow!("Error starting audit log writer: {}", e))?;
+
+        Ok(AuditLog {
+            path,
+            tx: Some(tx),
+            writer: Some(writer),
+        })
+    }
+
+    /// Queues `entry` for the writer. Entries that don't fit in the queue are
+    /// logged and counted as failed writes rather than failing the admission
+    /// request.
+    pub fn record(&self, entry: AuditEntry) {
+        let Some(tx) = &self.tx else {
+            return;
+        };
+        let (reason, entry) = match tx.try_send(entry) {
+            Ok(()) => return,
+            Err(TrySendError::Full(entry)) => ("the queue is full", entry),
+            Err(TrySendError::Disconnected(entry)) => ("the writer stopped", entry),
+        };
+        error!(
+            uid = entry.uid.as_str();
+            "dropping audit entry for {}: {}", self.path.display(), reason
+        );
+        metrics::observe_audit_write_failure();
+    }
+}
+
+impl Drop for AuditLog {
+    /// Closes the queue and waits for the writer to finish what's in it.
+    fn drop(&mut self) {
+        drop(self.tx.take());
+        if let Some(writer) = self.writer.take() {
+            let _ = writer.join();
+        }
+    }
+}
+
+/// The file end of an [`AuditLog`], owned by its writer thread.
+struct AuditWriter {
+    path: PathBuf,
+    max_bytes: u64,
+    max_files: usize,
+    file: AuditFile,
+}
+
+struct AuditFile {
+    file: File,
+    size: u64,
+}
+
+impl AuditWriter {
+    fn open(path: PathBuf, max_bytes: u64, max_files: usize) -> Result<AuditWriter> {
+        let file = open_append(&path)
+            .map_err(|e| anyhow!("Error opening audit log {}: {}", path.display(), e))?;
+
+        Ok(AuditWriter {
+            path,
+            max_bytes,
+            max_files,
+            file,
+        })
+    }
+
+    fn record(&mut self, entry: &AuditEntry) {
+        if let Err(e) = self.write(entry) {
+            error!(
+                uid = entry.uid.as_str();
+                "failed to write audit log {}: {}", self.path.display(), e
+            );
+            metrics::observe_audit_write_failure();
+        }
+    }
+
+    fn write(&mut self, entry: &AuditEntry) -> io::Result<()> {
+        let line = to_line(entry)?;
+
+        if self.file.size > 0 && self.file.size + line.len() as u64 > self.max_bytes {
+            self.file = self.rotate()?;
+        }
+
+        // Unbuffered, so the line reaches the OS as soon as it's dequeued.
+        self.file.file.write_all(&line)?;
+        self.file.size += line.len() as u64;
+        Ok(())
+    }
+
+    fn rotate(&self) -> io::Result<AuditFile> {
+        let rotated = |n: usize| {
+            let mut path = self.path.clone().into_os_string();
+            path.push(format!(".{}", n));
+            PathBuf::from(path)
+        };
+
+        if self.max_files == 0 {
+            fs::remove_file(&self.path)?;
+        } else {
+            for n in (1..self.max_files).rev() {
+                if rotated(n).exists() {
+                    fs::rename(rotated(n), rotated(n + 1))?;
+                }
+            }
+            fs::rename(&self.path, rotated(1))?;
+        }
+
+        open_append(&self.path)
+    }
+}
+
+fn to_line(entry: &AuditEntry) -> io::Result<Vec<u8>> {
+    let mut line = Vec::new();
+    entry.serialize(&mut Serializer::with_formatter(&mut line, JsonlFormatter))?;
+    line.push(b'\n');
+    Ok(line)
+}
+
+fn open_append(path: &Path) -> io::Result<AuditFile> {
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    let size = file.metadata()?.len();
+    Ok(AuditFile { file, size })
+}
+
+/// Compact JSON with a space after `:` and `,`, the way `requests.jsonl` is
+/// written.
+struct JsonlFormatter;
+
+impl Formatter for JsonlFormatter {
+    fn begin_array_value<W: ?Sized + Write>(
+        &mut self,
+        writer: &mut W,
+        first: bool,
+    ) -> io::Result<()> {
+        if first {
+            Ok(())
+        } else {
+            writer.write_all(b", ")
+        }
+    }
+
+    fn begin_object_key<W: ?Sized + Write>(
+        &mut self,
+        writer: &mut W,
+        first: bool,
+    ) -> io::Result<()> {
+        if first {
+            Ok(())
+        } else {
+            writer.write_all(b", ")
+        }
+    }
+
+    fn begin_object_value<W: ?Sized + Write>(&mut self, writer: &mut W) -> io::Result<()> {
+        writer.write_all(b": ")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::testing::TempDir;
+    use serde_json::json;
+
+    fn entry(uid: &str) -> AuditEntry {
+        AuditEntry {
+            timestamp: "2026-01-01T00:00:00.000Z".to_string(),
+            uid: uid.to_string(),
+            user: "system:serviceaccount:kube-system:replicaset-controller".to_string(),
+            kind: "Pod".to_string(),
+            namespace: "default".to_string(),
+            name: "test-pod".to_string(),
+            operation: "CREATE".to_string(),
+            decision: "injected".to_string(),
+            rule: "default".to_string(),
+            patch: Some(json!([{"op": "add", "path": "/spec/runtimeClassName", "value": "edera"}])),
+        }
+    }
+
+    fn read_entries(path: &Path) -> Vec<AuditEntry> {
+        fs::read_to_string(path)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_line_format() {
+        let dir = TempDir::new("audit-format");
+        let path = dir.join("audit.jsonl");
+        let audit = AuditLog::open(&path, 1 << 20, 3).unwrap();
+        audit.record(entry("a"));
+        drop(audit);
+
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            concat!(
+                r#"{"timestamp": "2026-01-01T00:00:00.000Z", "uid": "a", "#,
+                r#""user": "system:serviceaccount:kube-system:replicaset-controller", "#,
+                r#""kind": "Pod", "namespace": "default", "name": "test-pod", "#,
+                r#""operation": "CREATE", "decision": "injected", "rule": "default", "#,
+                r#""patch": [{"op": "add", "path": "/spec/runtimeClassName", "value": "edera"}]}"#,
+                "\n"
+            )
+        );
+        assert_eq!(read_entries(&path), vec![entry("a")]);
+    }
+
+    #[test]
+    fn test_rotation() {
+        let dir = TempDir::new("audit-rotation");
+        let path = dir.join("audit.jsonl");
+        let line_len = to_line(&entry("0")).unwrap().len() as u64;
+
+        // Room for two lines per file.
+        let audit = AuditLog::open(&path, line_len * 2, 2).unwrap();
+        for uid in ["0", "1", "2", "3", "4", "5", "6"] {
+            audit.record(entry(uid));
+        }
+        drop(audit);
+
+        let uids = |path: PathBuf| -> Vec<String> {
+            read_entries(&path)
+                .into_iter()
+                .map(|entry| entry.uid)
+                .collect()
+        };
+        assert_eq!(uids(dir.join("audit.jsonl.2")), vec!["2", "3"]);
+        assert_eq!(uids(dir.join("audit.jsonl.1")), vec!["4", "5"]);
+        assert_eq!(uids(path), vec!["6"]);
+        assert!(!dir.join("audit.jsonl.3").exists());
+
+        // Reopening continues the current file.
+        let audit = AuditLog::open(dir.join("audit.jsonl"), line_len * 2, 2).unwrap();
+        audit.record(entry("7"));
+        drop(audit);
+        assert_eq!(uids(dir.join("audit.jsonl")), vec!["6", "7"]);
+    }
+}
@@ -0,0 +1,153 @@
+use log::{info, warn};
+use std::{
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use super::metrics;
+use super::readyz::{self, Readiness};
+
+/// How often the sentinel file is checked for.
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+const ENV: &str = "WEBHOOK_KILL_SWITCH";
+const FILE: &str = "the sentinel file";
+const ADMIN: &str = "the admin API";
+
+/// Stops all mutation while engaged, so `/mutate` allows everything unpatched
+/// without the MutatingWebhookConfiguration being touched. Any of
+/// `WEBHOOK_KILL_SWITCH`, the sentinel file existing or the admin API engages
+/// it.
+pub struct KillSwitch {
+    env: bool,
+    file: Option<PathBuf>,
+    file_present: AtomicBool,
+    admin: AtomicBool,
+    /// The last state published, so changes are only logged once.
+    published: Mutex<Option<&'static str>>,
+    readiness: Arc<Readiness>,
+}
+
+impl KillSwitch {
+    pub fn new(env: bool, file: Option<PathBuf>, readiness: Arc<Readiness>) -> KillSwitch {
+        readiness.set(readyz::KILL_SWITCH, Ok("released".to_string()));
+        let kill_switch = KillSwitch {
+            env,
+            file,
+            file_present: AtomicBool::new(false),
+            admin: AtomicBool::new(false),
+            published: Mutex::new(None),
+            readiness,
+        };
+        kill_switch.poll();
+        kill_switch
+    }
+
+    /// What engaged the switch, or `None` while mutation is on.
+    pub fn engaged(&self) -> Option<&'static str> {
+        if self.env {
+            Some(ENV)
+        } else if self.file_present.load(Ordering::Relaxed) {
+            Some(FILE)
+        } else if self.admin.load(Ordering::Relaxed) {
+            Some(ADMIN)
+        } else {
+            None
+        }
+    }
+
+    /// Engages or releases the switch through the admin API. The other
+    /// sources keep it engaged regardless.
+    pub fn set(&self, engaged: bool) {
+        self.admin.store(engaged, Ordering::Relaxed);
+        self.publish();
+    }
+
+    /// Checks for the sentinel file and publishes the resulting state.
+    pub fn poll(&self) {
+        if let Some(file) = &self.file {
+            self.file_present.store(file.exists(), Ordering::Relaxed);
+        }
+        self.publish();
+    }
+
+    /// Polls for the sentinel file, if there is one, until the task is
+    /// dropped.
+    pub async fn watch(self: Arc<Self>) {
+        if self.file.is_none() {
+            return;
+        }
+
+        let mut interval = tokio::time::interval(POLL_INTERVAL);
+        loop {
+            interval.tick().await;
+            self.poll();
+        }
+    }
+
+    /// Reports the state in metrics and `/readyz`. It's never a reason to be
+    /// unready: endpoints dropping the pod would fail admission instead.
+    fn publish(&self) {
+        let engaged = self.engaged();
+        let mut published = self.published.lock().unwrap_or_else(|e| e.into_inner());
+        if *published == engaged {
+            return;
+        }
+
+        match engaged {
+            Some(source) => {
+                warn!(
+                    "kill switch engaged by {}, allowing every request unmutated",
+                    source
+                );
+                self.readiness
+                    .set(readyz::KILL_SWITCH, Ok(format!("engaged by {}", source)));
+            }
+            None => {
+                info!("kill switch released, mutating requests");
+                self.readiness
+                    .set(readyz::KILL_SWITCH, Ok("released".to_string()));
+            }
+        }
+        metrics::set_kill_switch_engaged(engaged.is_some());
+        *published = engaged;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::testing::TempDir;
+    use std::fs;
+
+    #[test]
+    fn test_kill_switch_sources() {
+        let dir = TempDir::new("kill-switch");
+        let sentinel = dir.join("kill-switch");
+
+        let kill_switch =
+            KillSwitch::new(false, Some(sentinel.clone()), Arc::new(Readiness::new()));
+        assert_eq!(kill_switch.engaged(), None);
+
+        kill_switch.set(true);
+        assert_eq!(kill_switch.engaged(), Some(ADMIN));
+        kill_switch.set(false);
+        assert_eq!(kill_switch.engaged(), None);
+
+        fs::write(&sentinel, "").unwrap();
+        kill_switch.poll();
+        assert_eq!(kill_switch.engaged(), Some(FILE));
+        fs::remove_file(&sentinel).unwrap();
+        kill_switch.poll();
+        assert_eq!(kill_switch.engaged(), None);
+
+        // Releasing through the admin API doesn't override the environment.
+        let kill_switch = KillSwitch::new(true, None, Arc::new(Readiness::new()));
+        kill_switch.set(false);
+        assert_eq!(kill_switch.engaged(), Some(ENV));
+    }
+}
@@ -0,0 +1,305 @@
+use anyhow::{anyhow, Result};
+use bytes::{Buf, Bytes, BytesMut};
+use futures_util::{Stream, StreamExt};
+use serde::Deserialize;
+use std::{fmt, pin::pin, sync::Arc, time::Duration};
+use tokio::time::Instant;
+use warp::{Filter, Rejection};
+
+/// What to answer when a request can't be evaluated in time.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FailureMode {
+    /// Allow the object unmutated, like `failurePolicy: Ignore`.
+    Open,
+    /// Deny the object, like `failurePolicy: Fail`.
+    Closed,
+}
+
+impl fmt::Display for FailureMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FailureMode::Open => write!(f, "open"),
+            FailureMode::Closed => write!(f, "closed"),
+        }
+    }
+}
+
+pub fn parse_failure_mode(value: &str) -> Result<FailureMode> {
+    match value {
+        "open" => Ok(FailureMode::Open),
+        "closed" => Ok(FailureMode::Closed),
+        other => Err(anyhow!(
+            "unsupported failure mode {}, expected open or closed",
+            other
+        )),
+    }
+}
+
+/// Bounds on the requests `/mutate` accepts.
+#[derive(Clone, Debug)]
+pub struct Limits {
+    /// Largest request body read before the request is rejected.
+    pub max_body_bytes: usize,
+    /// Budget used when the API server doesn't send one.
+    pub timeout: Duration,
+    pub failure_mode: FailureMode,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            // Room for an object and its old version at the API server's
+            // 3 MiB request limit.
+            max_body_bytes: 6 * 1024 * 1024,
+            // The API server's default `timeoutSeconds`.
+            timeout: Duration::from_secs(10),
+            failure_mode: FailureMode::Open,
+        }
+    }
+}
+
+impl Limits {
+    /// Starts the clock on a request with `budget`, or the configured timeout.
+    pub fn deadline(&self, budget: Option<Duration>) -> Deadline {
+        let budget = budget.unwrap_or(self.timeout);
+        let started = Instant::now();
+        Deadline {
+            started,
+            // Keep a tenth of the budget for the answer to reach the API
+            // server before it gives up on us.
+            at: started + budget.mul_f64(0.9),
+            budget,
+            failure_mode: self.failure_mode,
+        }
+    }
+}
+
+/// When a request has to be answered by.
+#[derive(Clone, Copy, Debug)]
+pub struct Deadline {
+    /// When the request arrived.
+    pub started: Instant,
+    pub at: Instant,
+    pub budget: Duration,
+    pub failure_mode: FailureMode,
+}
+
+impl Default for Deadline {
+    fn default() -> Self {
+        Limits::default().deadline(None)
+    }
+}
+
+impl Deadline {
+    pub fn expired(&self) -> bool {
+        Instant::now() >= self.at
+    }
+}
+
+/// The body grew past `max_body_bytes`. `uid` is the request uid, when it
+/// was found in the part that was read.
+#[derive(Debug)]
+pub struct BodyTooLarge {
+    pub uid: String,
+    pub max_bytes: usize,
+    pub deadline: Deadline,
+}
+
+impl warp::reject::Reject for BodyTooLarge {}
+
+/// The body was still arriving when the deadline passed. `uid` is the request
+/// uid, when it was found in the part that was read.
+#[derive(Debug)]
+pub struct DeadlineExceeded {
+    pub uid: String,
+    pub deadline: Deadline,
+}
+
+impl warp::reject::Reject for DeadlineExceeded {}
+
+/// The connection failed while the body was being read. `uid` is the request
+/// uid, when it was found in the part that was read.
+#[derive(Debug)]
+pub struct BodyReadError {
+    pub uid: String,
+    pub deadline: Deadline,
+    pub message: String,
+}
+
+impl warp::reject::Reject for BodyReadError {}
+
+#[derive(Deserialize)]
+struct WebhookQuery {
+    timeout: Option<String>,
+}
+
+/// The request's deadline. The API server passes the webhook's
+/// `timeoutSeconds` as a `timeout` query parameter, e.g. `?timeout=10s`.
+pub fn deadline(
+    limits: Arc<Limits>,
+) -> impl Filter<Extract = (Deadline,), Error = Rejection> + Clone {
+    warp::query::<WebhookQuery>().map(move |query: WebhookQuery| {
+        let budget = query
+            .timeout
+            .and_then(|timeout| humantime::parse_duration(&timeout).ok());
+        limits.deadline(budget)
+    })
+}
+
+/// The request's deadline and body. Reading stops with [`BodyTooLarge`] once
+/// the body passes `max_body_bytes`, [`DeadlineExceeded`] once the deadline
+/// passes, or [`BodyReadError`] if the connection fails.
+pub fn body(
+    limits: Arc<Limits>,
+) -> impl Filter<Extract = (Deadline, Bytes), Error = Rejection> + Clone {
+    let max_bytes = limits.max_body_bytes;
+    deadline(limits)
+        .and(warp::body::stream())
+        .and_then(move |deadline: Deadline, body| read_body(deadline, body, max_bytes))
+        .untuple_one()
+}
+
+async fn read_body(
+    deadline: Deadline,
+    body: impl Stream<Item = Result<impl Buf, warp::Error>>,
+    max_bytes: usize,
+) -> Result<(Deadline, Bytes), Rejection> {
+    let mut body = pin!(body);
+    let mut buffer = BytesMut::new();
+
+    let read = async {
+        while let Some(chunk) = body.next().await {
+            let mut chunk = match chunk {
+                Ok(chunk) => chunk,
+                Err(e) => {
+                    return Err(warp::reject::custom(BodyReadError {
+                        uid: uid_from_prefix(&buffer).unwrap_or_default(),
+                        deadline,
+                        message: e.to_string(),
+                    }))
+                }
+            };
+            let chunk = chunk.copy_to_bytes(chunk.remaining());
+            if buffer.len() + chunk.len() > max_bytes {
+                buffer.extend_from_slice(&chunk[..max_bytes - buffer.len()]);
+                return Err(warp::reject::custom(BodyTooLarge {
+                    uid: uid_from_prefix(&buffer).unwrap_or_default(),
+                    max_bytes,
+                    deadline,
+                }));
+            }
+            buffer.extend_from_slice(&chunk);
+        }
+        Ok(())
+    };
+
+    let read = tokio::time::timeout_at(deadline.at, read).await;
+    match read {
+        Ok(Ok(())) => Ok((deadline, buffer.freeze())),
+        Ok(Err(rejection)) => Err(rejection),
+        Err(_) => Err(warp::reject::custom(DeadlineExceeded {
+            uid: uid_from_prefix(&buffer).unwrap_or_default(),
+            deadline,
+        })),
+    }
+}
+
+/// Finds the request uid at the start of an AdmissionReview. The API server
+/// writes it ahead of the object, so it's within the part read before the
+/// limit unless the limit is tiny.
+pub fn uid_from_prefix(prefix: &[u8]) -> Option<String> {
+    let prefix = String::from_utf8_lossy(prefix);
+    let start = prefix.find("\"uid\"")? + "\"uid\"".len();
+    let value = prefix[start..].trim_start().strip_prefix(':')?;
+
+    serde_json::Deserializer::from_str(value)
+        .into_iter::<String>()
+        .next()?
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use warp::test::request;
+
+    #[tokio::test]
+    async fn test_deadline_from_query() {
+        let limits = Arc::new(Limits::default());
+
+        let filter = deadline(limits);
+
+        let from_query = request()
+            .path("/mutate?timeout=4s")
+            .filter(&filter)
+            .await
+            .unwrap();
+        assert_eq!(from_query.budget, Duration::from_secs(4));
+        assert!(from_query.at <= Instant::now() + Duration::from_millis(3600));
+
+        let default = request().path("/mutate").filter(&filter).await.unwrap();
+        assert_eq!(default.budget, Duration::from_secs(10));
+        assert_eq!(default.failure_mode, FailureMode::Open);
+    }
+
+    #[tokio::test]
+    async fn test_body_limit() {
+        let limits = Arc::new(Limits {
+            max_body_bytes: 64,
+            ..Default::default()
+        });
+        let review = format!(
+            r#"{{"kind":"AdmissionReview","request":{{"uid":"big-uid","object":{{"data":"{}"}}}}}}"#,
+            "x".repeat(64)
+        );
+
+        let rejection = request()
+            .method("POST")
+            .body(&review)
+            .filter(&body(limits.clone()))
+            .await
+            .unwrap_err();
+        let too_large = rejection.find::<BodyTooLarge>().unwrap();
+        assert_eq!(too_large.uid, "big-uid");
+        assert_eq!(too_large.max_bytes, 64);
+
+        let (_, read) = request()
+            .method("POST")
+            .body(&review[..64])
+            .filter(&body(limits))
+            .await
+            .unwrap();
+        assert_eq!(read, review[..64]);
+
+        assert_eq!(uid_from_prefix(br#"{"request": {"uid" : "a\"b"#), None);
+        assert_eq!(
+            uid_from_prefix(br#"{"request": {"uid" : "a\"b", "#),
+            Some("a\"b".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_body_deadline() {
+        let limits = Limits {
+            timeout: Duration::from_millis(50),
+            ..Default::default()
+        };
+        // The uid arrives, then the rest of the body never does.
+        let body = futures_util::stream::iter([Ok::<_, warp::Error>(Bytes::from_static(
+            br#"{"request":{"uid":"slow-uid","object":"#,
+        ))])
+        .chain(futures_util::stream::pending());
+
+        let rejection = read_body(limits.deadline(None), body, limits.max_body_bytes)
+            .await
+            .unwrap_err();
+        let exceeded = rejection.find::<DeadlineExceeded>().unwrap();
+        assert_eq!(exceeded.uid, "slow-uid");
+    }
+
+    #[test]
+    fn test_parse_failure_mode() {
+        assert_eq!(parse_failure_mode("closed").unwrap(), FailureMode::Closed);
+        assert!(parse_failure_mode("Fail").is_err());
+    }
+}
@@ -1,9 +1,181 @@
-use log::debug;
+use log::{debug, error, warn};
+use serde::Serialize;
+use std::{
+    convert::Infallible,
+    sync::{Arc, Mutex, MutexGuard},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio::time::{Instant, MissedTickBehavior};
 use warp::Filter;
 
-pub fn handler() -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
-    warp::get().and(warp::path("livez")).map(|| {
-        debug!("GET /livez");
-        warp::reply()
-    })
+use super::limits::Limits;
+use super::metrics;
+use super::mutate;
+use super::policy::SharedPolicy;
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct LivenessReport {
+    live: bool,
+    /// Seconds since a synthetic admission last completed.
+    synthetic_admission_age_seconds: f64,
+    /// How late the watchdog last woke up.
+    event_loop_lag_seconds: f64,
+}
+
+/// Runs a synthetic admission every `interval` so `/livez` can fail once none
+/// has completed for `threshold`, and tracks how late the runtime wakes it.
+#[derive(Debug)]
+pub struct Watchdog {
+    interval: Duration,
+    threshold: Duration,
+    state: Mutex<WatchdogState>,
+}
+
+#[derive(Debug)]
+struct WatchdogState {
+    last_completed: Instant,
+    lag: Duration,
+}
+
+impl Watchdog {
+    pub fn new(interval: Duration, threshold: Duration) -> Watchdog {
+        Watchdog {
+            interval,
+            threshold,
+            // Startup gets one threshold of grace.
+            state: Mutex::new(WatchdogState {
+                last_completed: Instant::now(),
+                lag: Duration::ZERO,
+            }),
+        }
+    }
+
+    /// Checks every `interval` until the task is dropped.
+    pub async fn run(self: Arc<Self>, policy: Arc<SharedPolicy>) {
+        let mut interval = tokio::time::interval(self.interval);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        loop {
+            let scheduled = interval.tick().await;
+            self.record_lag(scheduled.elapsed());
+            self.check(&policy).await;
+        }
+    }
+
+    /// Runs one synthetic admission, giving it until the next one is due.
+    async fn check(&self, policy: &SharedPolicy) {
+        let deadline = Limits::default().deadline(Some(self.interval));
+        match mutate::synthetic_admission(policy.current(), deadline).await {
+            Ok(()) => {
+                self.state().last_completed = Instant::now();
+                let now = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default();
+                metrics::set_watchdog_last_success(now.as_secs_f64());
+            }
+            Err(e) => error!("watchdog: {}", e),
+        }
+    }
+
+    fn record_lag(&self, lag: Duration) {
+        if lag > self.interval {
+            warn!(
+                "watchdog woke up {} late, the runtime may be blocked",
+                humantime::format_duration(lag)
+            );
+        }
+        metrics::set_event_loop_lag(lag.as_secs_f64());
+        self.state().lag = lag;
+    }
+
+    fn report(&self) -> LivenessReport {
+        let state = self.state();
+        let age = state.last_completed.elapsed();
+        LivenessReport {
+            live: age <= self.threshold,
+            synthetic_admission_age_seconds: age.as_secs_f64(),
+            event_loop_lag_seconds: state.lag.as_secs_f64(),
+        }
+    }
+
+    fn state(&self) -> MutexGuard<'_, WatchdogState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+pub fn handler(
+    watchdog: Arc<Watchdog>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::get()
+        .and(warp::path("livez"))
+        .and(with_watchdog(watchdog))
+        .map(|watchdog: Arc<Watchdog>| {
+            debug!("GET /livez");
+            let report = watchdog.report();
+            let status = if report.live {
+                warp::http::StatusCode::OK
+            } else {
+                error!(
+                    "no synthetic admission has completed for {:.0}s, failing liveness",
+                    report.synthetic_admission_age_seconds
+                );
+                warp::http::StatusCode::SERVICE_UNAVAILABLE
+            };
+            warp::reply::with_status(warp::reply::json(&report), status)
+        })
+}
+
+fn with_watchdog(
+    watchdog: Arc<Watchdog>,
+) -> impl Filter<Extract = (Arc<Watchdog>,), Error = Infallible> + Clone {
+    warp::any().map(move || watchdog.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::policy::Policy;
+    use serde_json::Value;
+    use warp::test::request;
+
+    #[tokio::test]
+    async fn test_livez_stale() {
+        let watchdog = Arc::new(Watchdog::new(
+            Duration::from_secs(1),
+            Duration::from_millis(50),
+        ));
+        let filter = handler(watchdog.clone());
+        let livez = || request().path("/livez").reply(&filter);
+
+        assert_eq!(livez().await.status(), 200);
+
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let response = livez().await;
+        assert_eq!(response.status(), 503);
+        let body: Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(body["live"], false);
+
+        watchdog
+            .check(&SharedPolicy::new(None, Policy::default()))
+            .await;
+        assert_eq!(livez().await.status(), 200);
+    }
+
+    #[tokio::test]
+    async fn test_watchdog_run() {
+        let watchdog = Arc::new(Watchdog::new(
+            Duration::from_millis(10),
+            Duration::from_millis(100),
+        ));
+        let task = tokio::spawn(
+            watchdog
+                .clone()
+                .run(Arc::new(SharedPolicy::new(None, Policy::default()))),
+        );
+
+        // Long past the startup grace, so only completed checks keep it live.
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        task.abort();
+        assert!(watchdog.report().live);
+    }
 }
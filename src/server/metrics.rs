@@ -0,0 +1,321 @@
+use log::{debug, error};
+use prometheus::{
+    Encoder, Gauge, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge, IntGaugeVec,
+    Opts, Registry, TextEncoder,
+};
+use std::{
+    sync::{LazyLock, Once},
+    time::Duration,
+};
+use warp::Filter;
+
+pub const INJECTED: &str = "injected";
+pub const SKIPPED: &str = "skipped";
+pub const DENIED: &str = "denied";
+pub const ERROR: &str = "error";
+
+pub const BODY_READ_ERROR: &str = "body_read_error";
+pub const BODY_TOO_LARGE: &str = "body_too_large";
+pub const DEADLINE_EXCEEDED: &str = "deadline_exceeded";
+pub const INVALID_REVIEW: &str = "invalid_review";
+pub const PANIC: &str = "panic";
+
+struct Metrics {
+    registry: Registry,
+    admission_requests: IntCounterVec,
+    admission_skips: IntCounterVec,
+    admission_duration: HistogramVec,
+    deserialize_failures: IntCounter,
+    admission_failures: IntCounterVec,
+    tls_certificate_expiry: Gauge,
+    /// The expiry is only registered once a certificate is loaded, so
+    /// webhooks serving without TLS don't export an expiry of 0.
+    tls_certificate_expiry_registered: Once,
+    tls_handshake_failures: IntCounterVec,
+    audit_write_failures: IntCounter,
+    kill_switch_engaged: IntGauge,
+    build_info: IntGaugeVec,
+    event_loop_lag: Gauge,
+    watchdog_last_success: Gauge,
+}
+
+impl Metrics {
+    fn new() -> prometheus::Result<Metrics> {
+        let registry = Registry::new();
+
+        let admission_requests = IntCounterVec::new(
+            Opts::new(
+                "protect_webhook_admission_requests_total",
+                "Admission requests handled, by kind, namespace, operation and decision",
+            ),
+            &["kind", "namespace", "operation", "decision"],
+        )?;
+        let admission_skips = IntCounterVec::new(
+            Opts::new(
+                "protect_webhook_admission_skips_total",
+                "Admission requests left unmutated, by reason",
+            ),
+            &["reason"],
+        )?;
+        let admission_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "protect_webhook_admission_duration_seconds",
+                "Time spent deciding and answering admission requests",
+            )
+            .buckets(vec![
+                0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0,
+            ]),
+            &["decision"],
+        )?;
+        let deserialize_failures = IntCounter::new(
+            "protect_webhook_deserialize_failures_total",
+            "Request bodies that could not be deserialized into an AdmissionReview",
+        )?;
+        let admission_failures = IntCounterVec::new(
+            Opts::new(
+                "protect_webhook_admission_failures_total",
+                "Admission requests answered without evaluating the policy, by reason",
+            ),
+            &["reason"],
+        )?;
+        let tls_certificate_expiry = Gauge::new(
+            "protect_webhook_tls_certificate_expiry_timestamp_seconds",
+            "Expiry of the serving certificate as a unix timestamp",
+        )?;
+        let tls_handshake_failures = IntCounterVec::new(
+            Opts::new(
+                "protect_webhook_tls_handshake_failures_total",
+                "TLS handshakes rejected or abandoned, by reason",
+            ),
+            &["reason"],
+        )?;
+        let audit_write_failures = IntCounter::new(
+            "protect_webhook_audit_write_failures_total",
+            "Admission decisions that could not be written to the audit log or were dropped",
+        )?;
+        let kill_switch_engaged = IntGauge::new(
+            "protect_webhook_kill_switch_engaged",
+            "Whether the kill switch is stopping all mutation",
+        )?;
+        let build_info = IntGaugeVec::new(
+            Opts::new(
+                "protect_webhook_build_info",
+                "Always 1, labeled with the webhook build and the hash of the active policy",
+            ),
+            &[
+                "version",
+                "git_commit",
+                "build_time",
+                "features",
+                "policy_hash",
+            ],
+        )?;
+
+        let event_loop_lag = Gauge::new(
+            "protect_webhook_event_loop_lag_seconds",
+            "How late the watchdog last woke up on the async runtime",
+        )?;
+        let watchdog_last_success = Gauge::new(
+            "protect_webhook_watchdog_last_success_timestamp_seconds",
+            "When the watchdog's synthetic admission last completed as a unix timestamp",
+        )?;
+
+        registry.register(Box::new(admission_requests.clone()))?;
+        registry.register(Box::new(admission_skips.clone()))?;
+        registry.register(Box::new(admission_duration.clone()))?;
+        registry.register(Box::new(deserialize_failures.clone()))?;
+        registry.register(Box::new(admission_failures.clone()))?;
+        registry.register(Box::new(tls_handshake_failures.clone()))?;
+        registry.register(Box::new(audit_write_failures.clone()))?;
+        registry.register(Box::new(kill_switch_engaged.clone()))?;
+        registry.register(Box::new(build_info.clone()))?;
+        registry.register(Box::new(event_loop_lag.clone()))?;
+        registry.register(Box::new(watchdog_last_success.clone()))?;
+
+        Ok(Metrics {
+            registry,
+            admission_requests,
+            admission_skips,
+            admission_duration,
+            deserialize_failures,
+            admission_failures,
+            tls_certificate_expiry,
+            tls_certificate_expiry_registered: Once::new(),
+            tls_handshake_failures,
+            audit_write_failures,
+            kill_switch_engaged,
+            build_info,
+            event_loop_lag,
+            watchdog_last_success,
+        })
+    }
+}
+
+impl Metrics {
+    fn set_tls_certificate_expiry(&self, timestamp: i64) {
+        self.tls_certificate_expiry.set(timestamp as f64);
+        self.tls_certificate_expiry_registered.call_once(|| {
+            if let Err(e) = self
+                .registry
+                .register(Box::new(self.tls_certificate_expiry.clone()))
+            {
+                error!("failed to register the certificate expiry metric: {}", e);
+            }
+        });
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut buffer = Vec::new();
+        if let Err(e) = TextEncoder::new().encode(&self.registry.gather(), &mut buffer) {
+            error!("failed to encode metrics: {}", e);
+        }
+        buffer
+    }
+}
+
+static METRICS: LazyLock<Metrics> =
+    LazyLock::new(|| Metrics::new().expect("failed to register metrics"));
+
+pub fn observe_admission(
+    kind: &str,
+    namespace: &str,
+    operation: &str,
+    decision: &str,
+    elapsed: Duration,
+) {
+    METRICS
+        .admission_requests
+        .with_label_values(&[kind, namespace, operation, decision])
+        .inc();
+    observe_admission_duration(decision, elapsed);
+}
+
+/// Times an answer without counting it in `admission_requests_total`, for
+/// requests whose kind, namespace and operation aren't known.
+pub fn observe_admission_duration(decision: &str, elapsed: Duration) {
+    METRICS
+        .admission_duration
+        .with_label_values(&[decision])
+        .observe(elapsed.as_secs_f64());
+}
+
+pub fn observe_skip(reason: &str) {
+    METRICS.admission_skips.with_label_values(&[reason]).inc();
+}
+
+pub fn observe_deserialize_failure() {
+    METRICS.deserialize_failures.inc();
+}
+
+pub fn observe_admission_failure(reason: &str) {
+    METRICS
+        .admission_failures
+        .with_label_values(&[reason])
+        .inc();
+}
+
+pub fn set_tls_certificate_expiry(timestamp: i64) {
+    METRICS.set_tls_certificate_expiry(timestamp);
+}
+
+pub fn observe_tls_handshake_failure(reason: &str) {
+    METRICS
+        .tls_handshake_failures
+        .with_label_values(&[reason])
+        .inc();
+}
+
+#[cfg(test)]
+pub fn tls_handshake_failures(reason: &str) -> u64 {
+    METRICS
+        .tls_handshake_failures
+        .with_label_values(&[reason])
+        .get()
+}
+
+pub fn observe_audit_write_failure() {
+    METRICS.audit_write_failures.inc();
+}
+
+pub fn set_kill_switch_engaged(engaged: bool) {
+    METRICS.kill_switch_engaged.set(engaged as i64);
+}
+
+/// Replaces the build info series, so there's only ever the one for the
+/// active policy.
+pub fn set_build_info(
+    version: &str,
+    git_commit: &str,
+    build_time: &str,
+    features: &str,
+    policy_hash: &str,
+) {
+    METRICS.build_info.reset();
+    METRICS
+        .build_info
+        .with_label_values(&[version, git_commit, build_time, features, policy_hash])
+        .set(1);
+}
+
+pub fn set_event_loop_lag(seconds: f64) {
+    METRICS.event_loop_lag.set(seconds);
+}
+
+pub fn set_watchdog_last_success(timestamp: f64) {
+    METRICS.watchdog_last_success.set(timestamp);
+}
+
+pub fn handler() -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::get().and(warp::path("metrics")).map(|| {
+        debug!("GET /metrics");
+        warp::reply::with_header(METRICS.encode(), "content-type", prometheus::TEXT_FORMAT)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use warp::test::request;
+
+    #[tokio::test]
+    async fn test_metrics_endpoint() {
+        observe_admission(
+            "Pod",
+            "metrics-test",
+            "CREATE",
+            INJECTED,
+            Duration::from_millis(2),
+        );
+        observe_skip("mirror_pod");
+
+        let response = request()
+            .method("GET")
+            .path("/metrics")
+            .reply(&handler())
+            .await;
+
+        assert_eq!(response.status(), 200);
+        let body = String::from_utf8(response.body().to_vec()).unwrap();
+        assert!(body.contains(
+            r#"protect_webhook_admission_requests_total{decision="injected",kind="Pod",namespace="metrics-test",operation="CREATE"}"#
+        ));
+        assert!(body.contains(r#"protect_webhook_admission_skips_total{reason="mirror_pod"}"#));
+        assert!(body.contains("protect_webhook_admission_duration_seconds_bucket"));
+        assert!(body.contains("protect_webhook_deserialize_failures_total"));
+    }
+
+    #[test]
+    fn test_certificate_expiry_only_with_tls() {
+        let metrics = Metrics::new().unwrap();
+        let name = "protect_webhook_tls_certificate_expiry_timestamp_seconds";
+
+        let without_tls = String::from_utf8(metrics.encode()).unwrap();
+        assert!(without_tls.contains("protect_webhook_kill_switch_engaged"));
+        assert!(!without_tls.contains(name));
+
+        metrics.set_tls_certificate_expiry(1_900_000_000);
+        metrics.set_tls_certificate_expiry(1_900_000_001);
+        let with_tls = String::from_utf8(metrics.encode()).unwrap();
+        assert!(with_tls.contains(&format!("{} 1900000001", name)));
+    }
+}
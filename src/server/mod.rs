@@ -1,16 +1,78 @@
 use anyhow::{anyhow, Result};
-use log::info;
-use std::{env, fs};
-use warp::Filter;
+use log::{error, info, warn};
+use opentelemetry_sdk::trace::SdkTracerProvider;
+use std::{
+    convert::Infallible, env, fs, net::SocketAddr, path::PathBuf, sync::Arc, time::Duration,
+};
+use tokio::task::JoinSet;
+use tokio_rustls::rustls::ServerConfig;
+use warp::{
+    hyper::{
+        service::{make_service_fn, service_fn, Service},
+        Body, Request, Server,
+    },
+    Filter,
+};
 
+use admin::Admin;
+use audit::AuditLog;
+use killswitch::KillSwitch;
+use limits::Limits;
+use livez::Watchdog;
+use policy::{Policy, SharedPolicy};
+use readyz::Readiness;
+use recent::RecentDecisions;
+use redact::Redactor;
+use shutdown::Shutdown;
+
+mod accept;
+mod admin;
+mod admission;
+mod audit;
 mod healthz;
+mod killswitch;
+mod limits;
 mod livez;
+mod metrics;
 mod mutate;
+mod patch;
+mod policy;
+mod readyz;
+mod recent;
+mod redact;
+mod shutdown;
+mod telemetry;
+#[cfg(test)]
+mod testing;
+mod tls;
+mod unix;
+mod version;
+
+fn routes(
+    policy: Arc<SharedPolicy>,
+    redactor: Arc<Redactor>,
+    audit: Option<Arc<AuditLog>>,
+    recent: Arc<RecentDecisions>,
+    kill_switch: Arc<KillSwitch>,
+    limits: Arc<Limits>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    mutate::handler(policy, redactor, audit, recent, kill_switch, limits)
+}
 
-fn routes() -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
-    mutate::handler()
-        .or(livez::handler())
+/// Routes served over plain HTTP so kubelet probes and Prometheus don't need TLS.
+fn admin_routes(
+    watchdog: Arc<Watchdog>,
+    readiness: Arc<Readiness>,
+    policy: Arc<SharedPolicy>,
+    admin: Arc<Admin>,
+    insecure_http: bool,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    livez::handler(watchdog)
         .or(healthz::handler())
+        .or(readyz::handler(readiness))
+        .or(metrics::handler())
+        .or(version::handler(policy, insecure_http))
+        .or(admin::handler(admin))
 }
 
 fn set_certs_dir() -> Result<String> {
@@ -60,22 +122,664 @@ fn set_key_path(certs_dir: &String) -> Result<String> {
     Ok(key_path)
 }
 
-pub async fn start() -> Result<()> {
-    let certs_dir = set_certs_dir()?;
-    let crt_path = set_crt_path(&certs_dir)?;
-    let key_path = set_key_path(&certs_dir)?;
+/// Parses a comma separated list of listen addresses, e.g. `0.0.0.0:8443` or
+/// `[::]:8443` for dual-stack.
+fn set_listen_addrs(var: &str, default: &str) -> Result<Vec<SocketAddr>> {
+    let addrs = env::var(var).unwrap_or(default.to_string());
+
+    let addrs = addrs
+        .split(',')
+        .map(str::trim)
+        .filter(|addr| !addr.is_empty())
+        .map(|addr| {
+            addr.parse()
+                .map_err(|e| anyhow!("Error parsing {} address {}: {}", var, addr, e))
+        })
+        .collect::<Result<Vec<SocketAddr>>>()?;
+
+    if addrs.is_empty() {
+        return Err(anyhow!("{} does not contain any addresses", var));
+    }
+
+    Ok(addrs)
+}
+
+/// Client certificate verification, enabled by setting `WEBHOOK_CLIENT_CA_FILE`.
+fn set_client_auth() -> Result<Option<tls::ClientAuth>> {
+    let Ok(ca_path) = env::var("WEBHOOK_CLIENT_CA_FILE") else {
+        return Ok(None);
+    };
+
+    let meta = match fs::metadata(&ca_path) {
+        Err(e) => return Err(anyhow!("Error reading metadata for {}: {}", ca_path, e)),
+        Ok(meta) => meta,
+    };
+
+    if !meta.is_file() {
+        return Err(anyhow!("{} is not a file", ca_path));
+    }
+
+    let allowed_names = split_list(&env::var("WEBHOOK_CLIENT_ALLOWED_NAMES").unwrap_or_default())
+        .into_iter()
+        .map(str::to_string)
+        .collect();
+
+    Ok(Some(tls::ClientAuth {
+        ca_path,
+        allowed_names,
+    }))
+}
+
+fn set_tls_settings() -> Result<tls::TlsSettings> {
+    let mut settings = tls::TlsSettings::default();
+
+    if let Ok(min_version) = env::var("WEBHOOK_TLS_MIN_VERSION") {
+        settings.min_version = tls::parse_min_version(min_version.trim())
+            .map_err(|e| anyhow!("Error parsing WEBHOOK_TLS_MIN_VERSION: {}", e))?;
+    }
+
+    if let Ok(cipher_suites) = env::var("WEBHOOK_TLS_CIPHER_SUITES") {
+        settings.cipher_suites = tls::parse_cipher_suites(&split_list(&cipher_suites))
+            .map_err(|e| anyhow!("Error parsing WEBHOOK_TLS_CIPHER_SUITES: {}", e))?;
+    }
+
+    if let Ok(alpn) = env::var("WEBHOOK_TLS_ALPN") {
+        settings.alpn_protocols = tls::parse_alpn_protocols(&split_list(&alpn))
+            .map_err(|e| anyhow!("Error parsing WEBHOOK_TLS_ALPN: {}", e))?;
+    }
+
+    Ok(settings)
+}
+
+fn split_list(value: &str) -> Vec<&str> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .collect()
+}
+
+fn set_drain_period() -> Result<Duration> {
+    let drain_seconds = env::var("WEBHOOK_DRAIN_SECONDS").unwrap_or("5".to_string());
+
+    drain_seconds.parse().map(Duration::from_secs).map_err(|e| {
+        anyhow!(
+            "Error parsing WEBHOOK_DRAIN_SECONDS {}: {}",
+            drain_seconds,
+            e
+        )
+    })
+}
+
+/// How long stopped listeners get to finish open connections before they're
+/// closed.
+fn set_shutdown_timeout() -> Result<Duration> {
+    let timeout = env::var("WEBHOOK_SHUTDOWN_TIMEOUT_SECONDS").unwrap_or("10".to_string());
+
+    timeout.parse().map(Duration::from_secs).map_err(|e| {
+        anyhow!(
+            "Error parsing WEBHOOK_SHUTDOWN_TIMEOUT_SECONDS {}: {}",
+            timeout,
+            e
+        )
+    })
+}
+
+/// Body size limit, and the deadline and failure mode for requests the API
+/// server doesn't send a timeout with.
+fn set_limits() -> Result<Limits> {
+    let mut limits = Limits::default();
+
+    if let Ok(max_body_bytes) = env::var("WEBHOOK_MAX_BODY_BYTES") {
+        limits.max_body_bytes = max_body_bytes.parse().map_err(|e| {
+            anyhow!(
+                "Error parsing WEBHOOK_MAX_BODY_BYTES {}: {}",
+                max_body_bytes,
+                e
+            )
+        })?;
+    }
+
+    if let Ok(timeout_seconds) = env::var("WEBHOOK_TIMEOUT_SECONDS") {
+        limits.timeout = timeout_seconds
+            .parse()
+            .map(Duration::from_secs)
+            .map_err(|e| {
+                anyhow!(
+                    "Error parsing WEBHOOK_TIMEOUT_SECONDS {}: {}",
+                    timeout_seconds,
+                    e
+                )
+            })?;
+    }
+
+    if let Ok(failure_mode) = env::var("WEBHOOK_FAILURE_MODE") {
+        limits.failure_mode = limits::parse_failure_mode(failure_mode.trim())
+            .map_err(|e| anyhow!("Error parsing WEBHOOK_FAILURE_MODE: {}", e))?;
+    }
+
+    Ok(limits)
+}
+
+/// Redaction applied to request bodies logged at debug level.
+fn set_redactor() -> Result<Redactor> {
+    let paths = env::var("WEBHOOK_REDACT_PATHS").unwrap_or_default();
+    let max_bytes = env::var("WEBHOOK_LOG_BODY_LIMIT").unwrap_or("4096".to_string());
+
+    let max_bytes = max_bytes
+        .parse()
+        .map_err(|e| anyhow!("Error parsing WEBHOOK_LOG_BODY_LIMIT {}: {}", max_bytes, e))?;
+
+    Redactor::new(&split_list(&paths), max_bytes)
+        .map_err(|e| anyhow!("Error parsing WEBHOOK_REDACT_PATHS: {}", e))
+}
+
+/// Decision audit log, enabled by setting `WEBHOOK_AUDIT_FILE`.
+fn set_audit_log() -> Result<Option<AuditLog>> {
+    let Ok(path) = env::var("WEBHOOK_AUDIT_FILE") else {
+        return Ok(None);
+    };
+
+    let max_bytes = env::var("WEBHOOK_AUDIT_MAX_BYTES").unwrap_or("104857600".to_string());
+    let max_bytes = max_bytes
+        .parse()
+        .map_err(|e| anyhow!("Error parsing WEBHOOK_AUDIT_MAX_BYTES {}: {}", max_bytes, e))?;
+    let max_files = env::var("WEBHOOK_AUDIT_MAX_FILES").unwrap_or("5".to_string());
+    let max_files = max_files
+        .parse()
+        .map_err(|e| anyhow!("Error parsing WEBHOOK_AUDIT_MAX_FILES {}: {}", max_files, e))?;
+
+    let audit = AuditLog::open(&path, max_bytes, max_files)?;
+    info!("writing audit log to {}", path);
+    Ok(Some(audit))
+}
+
+/// How many decisions to keep in memory for the admin API's `/decisions`.
+fn set_recent_decisions() -> Result<RecentDecisions> {
+    let capacity = env::var("WEBHOOK_RECENT_DECISIONS").unwrap_or("1000".to_string());
+
+    capacity
+        .parse()
+        .map(RecentDecisions::new)
+        .map_err(|e| anyhow!("Error parsing WEBHOOK_RECENT_DECISIONS {}: {}", capacity, e))
+}
+
+/// Kill switch engaged by `WEBHOOK_KILL_SWITCH=true` or the file named by
+/// `WEBHOOK_KILL_SWITCH_FILE` existing.
+fn set_kill_switch(readiness: Arc<Readiness>) -> Result<KillSwitch> {
+    let engaged = env::var("WEBHOOK_KILL_SWITCH").unwrap_or("false".to_string());
+    let engaged = engaged
+        .parse()
+        .map_err(|e| anyhow!("Error parsing WEBHOOK_KILL_SWITCH {}: {}", engaged, e))?;
+    let file = env::var("WEBHOOK_KILL_SWITCH_FILE").ok().map(PathBuf::from);
+
+    if let Some(file) = &file {
+        info!("kill switch engages while {} exists", file.display());
+    }
+    Ok(KillSwitch::new(engaged, file, readiness))
+}
+
+/// How often the watchdog runs a synthetic admission, and how long `/livez`
+/// waits for one to complete before failing.
+fn set_watchdog() -> Result<Watchdog> {
+    let interval = env::var("WEBHOOK_WATCHDOG_INTERVAL_SECONDS").unwrap_or("5".to_string());
+    let interval = interval.parse().map(Duration::from_secs).map_err(|e| {
+        anyhow!(
+            "Error parsing WEBHOOK_WATCHDOG_INTERVAL_SECONDS {}: {}",
+            interval,
+            e
+        )
+    })?;
+    let threshold = env::var("WEBHOOK_WATCHDOG_THRESHOLD_SECONDS").unwrap_or("30".to_string());
+    let threshold = threshold.parse().map(Duration::from_secs).map_err(|e| {
+        anyhow!(
+            "Error parsing WEBHOOK_WATCHDOG_THRESHOLD_SECONDS {}: {}",
+            threshold,
+            e
+        )
+    })?;
+
+    if interval.is_zero() || threshold <= interval {
+        return Err(anyhow!(
+            "WEBHOOK_WATCHDOG_THRESHOLD_SECONDS must be longer than a non-zero WEBHOOK_WATCHDOG_INTERVAL_SECONDS"
+        ));
+    }
+    Ok(Watchdog::new(interval, threshold))
+}
+
+/// OTLP trace export, enabled by setting `WEBHOOK_OTLP_ENDPOINT`.
+fn set_tracing() -> Result<Option<SdkTracerProvider>> {
+    let Ok(endpoint) = env::var("WEBHOOK_OTLP_ENDPOINT") else {
+        return Ok(None);
+    };
+
+    let provider = telemetry::init(&endpoint)?;
+    info!("exporting traces to {}", endpoint);
+    Ok(Some(provider))
+}
+
+fn set_policy() -> Result<SharedPolicy> {
+    let Ok(policy_file) = env::var("WEBHOOK_POLICY_FILE") else {
+        info!("WEBHOOK_POLICY_FILE not set, using default policy");
+        return Ok(SharedPolicy::new(None, Policy::default()));
+    };
+
+    let policy = Policy::load(&policy_file)?;
+    info!("loaded policy from {}", policy_file);
+    Ok(SharedPolicy::new(Some(policy_file), policy))
+}
+
+/// Bearer token for the admin API, which is disabled unless
+/// `WEBHOOK_ADMIN_TOKEN_FILE` is set.
+fn set_admin_token() -> Result<Option<String>> {
+    let Ok(token_file) = env::var("WEBHOOK_ADMIN_TOKEN_FILE") else {
+        info!("WEBHOOK_ADMIN_TOKEN_FILE not set, admin API disabled");
+        return Ok(None);
+    };
+
+    let token = match fs::read_to_string(&token_file) {
+        Err(e) => return Err(anyhow!("Error reading admin token {}: {}", token_file, e)),
+        Ok(token) => token.trim().to_string(),
+    };
+
+    if token.is_empty() {
+        return Err(anyhow!("Admin token {} is empty", token_file));
+    }
+
+    info!("admin API enabled");
+    Ok(Some(token))
+}
+
+/// Development mode serving `/mutate` over plain HTTP on `WEBHOOK_ADDR`,
+/// enabled by setting `WEBHOOK_INSECURE_HTTP`. Refused inside a cluster,
+/// where the API server would be talking to it.
+fn set_insecure_http() -> Result<bool> {
+    let insecure = env::var("WEBHOOK_INSECURE_HTTP").unwrap_or("false".to_string());
+    let insecure = insecure
+        .parse()
+        .map_err(|e| anyhow!("Error parsing WEBHOOK_INSECURE_HTTP {}: {}", insecure, e))?;
+    check_insecure_http(insecure, env::var("KUBERNETES_SERVICE_HOST").ok())
+}
+
+/// Refuses plain HTTP when `KUBERNETES_SERVICE_HOST` shows the webhook is
+/// running in a pod.
+fn check_insecure_http(insecure: bool, cluster_host: Option<String>) -> Result<bool> {
+    if !insecure {
+        return Ok(false);
+    }
+
+    if let Some(host) = cluster_host {
+        return Err(anyhow!(
+            "WEBHOOK_INSECURE_HTTP is for local development, refusing to start inside a cluster (KUBERNETES_SERVICE_HOST is {})",
+            host
+        ));
+    }
+    warn!("!!! WEBHOOK_INSECURE_HTTP is set: serving /mutate over plain HTTP without TLS !!!");
+    warn!("!!! never expose this webhook to an API server in this mode !!!");
+    Ok(true)
+}
+
+/// Plain HTTP listener on a Unix domain socket, enabled by setting
+/// `WEBHOOK_UNIX_SOCKET`, and the octal permissions of the socket file.
+fn set_unix_socket() -> Result<Option<(PathBuf, u32)>> {
+    let Ok(path) = env::var("WEBHOOK_UNIX_SOCKET") else {
+        return Ok(None);
+    };
+    let mode = env::var("WEBHOOK_UNIX_SOCKET_MODE").unwrap_or("0600".to_string());
+    let mode = u32::from_str_radix(&mode, 8)
+        .ok()
+        .filter(|mode| *mode <= 0o777)
+        .ok_or_else(|| {
+            anyhow!(
+                "Error parsing WEBHOOK_UNIX_SOCKET_MODE {}: expected octal permissions, e.g. 0660",
+                mode
+            )
+        })?;
+    Ok(Some((PathBuf::from(path), mode)))
+}
+
+/// Loads the serving certificate from `certs_dir` and builds the TLS config
+/// for the `WEBHOOK_ADDR` listeners.
+fn load_serving_tls(
+    certs_dir: &String,
+    readiness: &Readiness,
+) -> Result<(Arc<tls::ServingCert>, Arc<ServerConfig>)> {
+    let crt_path = set_crt_path(certs_dir)?;
+    let key_path = set_key_path(certs_dir)?;
     info!("configured certs directory to: {}", certs_dir);
+    let tls_settings = set_tls_settings()?;
+    let client_auth = set_client_auth()?;
+
+    let key_pair = tls::load_key_pair(&crt_path, &key_path)?;
+    metrics::set_tls_certificate_expiry(key_pair.expiry);
+    readiness.set_certificate_expiry(key_pair.expiry);
+    match &client_auth {
+        Some(client_auth) if client_auth.allowed_names.is_empty() => {
+            info!(
+                "verifying client certificates against {}",
+                client_auth.ca_path
+            )
+        }
+        Some(client_auth) => info!(
+            "verifying client certificates against {}, allowing {}",
+            client_auth.ca_path,
+            client_auth.allowed_names.join(", ")
+        ),
+        None => info!("client certificates are not verified"),
+    }
+    let cert = Arc::new(tls::ServingCert::new(crt_path, key_path, key_pair)?);
+    let tls_config = tls::server_config(cert.clone(), &tls_settings, client_auth.as_ref())?;
+    Ok((cert, tls_config))
+}
+
+/// Serves `routes` over plain HTTP on `addrs` for `WEBHOOK_INSECURE_HTTP`,
+/// returning the addresses bound.
+fn bind_insecure_listeners(
+    routes: impl Filter<Extract = impl warp::Reply, Error = warp::Rejection>
+        + Clone
+        + Send
+        + Sync
+        + 'static,
+    addrs: Vec<SocketAddr>,
+    shutdown: &Shutdown,
+    servers: &mut JoinSet<()>,
+) -> Result<Vec<SocketAddr>> {
+    let mut bound = Vec::new();
+    for addr in addrs {
+        let (addr, server) = warp::serve(routes.clone())
+            .try_bind_with_graceful_shutdown(addr, shutdown.signal())
+            .map_err(|e| anyhow!("Error binding listener to {}: {}", addr, e))?;
+        warn!("listening on {} over plain HTTP", addr);
+        servers.spawn(server);
+        bound.push(addr);
+    }
+    Ok(bound)
+}
 
-    // TODO: Make healthz and livez listen on http rather than https if they need to do more
-    let routes = routes();
+/// Keeps serving for `drain_period`, then stops the listeners and waits up to
+/// `timeout` for their connections to finish. Returns whether they all did.
+async fn stop(
+    drain_period: Duration,
+    timeout: Duration,
+    shutdown: &Shutdown,
+    servers: &mut JoinSet<()>,
+) -> bool {
+    tokio::time::sleep(drain_period).await;
 
-    info!("listening on 8443");
-    warp::serve(routes)
-        .tls()
-        .cert_path(crt_path)
-        .key_path(key_path)
-        .run(([0, 0, 0, 0], 8443))
-        .await;
+    info!("stopping listeners");
+    shutdown.trigger();
+    let drained = async { while servers.join_next().await.is_some() {} };
+    if tokio::time::timeout(timeout, drained).await.is_err() {
+        warn!(
+            "connections still open after {}, closing them",
+            humantime::format_duration(timeout)
+        );
+        return false;
+    }
+    true
+}
+
+pub async fn start() -> Result<()> {
+    let insecure_http = set_insecure_http()?;
+    let unix_socket = set_unix_socket()?;
+    // Certificates are only optional with a Unix socket to serve on instead.
+    let certs_dir = match set_certs_dir() {
+        _ if insecure_http => None,
+        Ok(certs_dir) => Some(certs_dir),
+        Err(e) if unix_socket.is_some() => {
+            warn!("{}, serving /mutate on the Unix socket only", e);
+            None
+        }
+        Err(e) => return Err(e),
+    };
+    let addrs = set_listen_addrs("WEBHOOK_ADDR", "0.0.0.0:8443")?;
+    let admin_addrs = set_listen_addrs("WEBHOOK_ADMIN_ADDR", "0.0.0.0:8080")?;
+    let drain_period = set_drain_period()?;
+    let shutdown_timeout = set_shutdown_timeout()?;
+    let limits = Arc::new(set_limits()?);
+    let redactor = Arc::new(set_redactor()?);
+    let tracer_provider = set_tracing()?;
+    let audit = set_audit_log()?.map(Arc::new);
+    let recent = Arc::new(set_recent_decisions()?);
+    let admin_token = set_admin_token()?;
+    let readiness = Arc::new(Readiness::new());
+    let kill_switch = Arc::new(set_kill_switch(readiness.clone())?);
+    let watchdog = Arc::new(set_watchdog()?);
+
+    let serving_tls = match &certs_dir {
+        Some(certs_dir) => Some(load_serving_tls(certs_dir, &readiness)?),
+        None => {
+            let message = if insecure_http {
+                "disabled, serving plain HTTP"
+            } else {
+                "disabled"
+            };
+            readiness.set(readyz::TLS, Ok(message.to_string()));
+            None
+        }
+    };
+
+    let policy = Arc::new(set_policy()?);
+    readiness.set(readyz::POLICY, Ok("loaded".to_string()));
+    let policy_hash = policy.current().hash();
+    version::publish(&policy_hash);
+    info!(
+        "protect-webhook {} ({}, built {}) with policy {}",
+        version::VERSION,
+        version::GIT_COMMIT,
+        version::BUILD_TIME,
+        policy_hash
+    );
+
+    match mutate::self_test(&policy.current()) {
+        Ok(()) => readiness.set(readyz::SELF_TEST, Ok("passed".to_string())),
+        Err(e) => {
+            error!("self-test failed: {}", e);
+            readiness.set(readyz::SELF_TEST, Err(e.to_string()));
+        }
+    }
+
+    let shutdown = Shutdown::new();
+    let mut servers = JoinSet::new();
+
+    let admin = Arc::new(Admin::new(
+        admin_token,
+        policy.clone(),
+        serving_tls.as_ref().map(|(cert, _)| cert.clone()),
+        readiness.clone(),
+        recent.clone(),
+        kill_switch.clone(),
+    ));
+    let admin_routes = admin_routes(
+        watchdog.clone(),
+        readiness.clone(),
+        policy.clone(),
+        admin,
+        insecure_http,
+    );
+    for addr in admin_addrs {
+        let (addr, server) = warp::serve(admin_routes.clone())
+            .try_bind_with_graceful_shutdown(addr, shutdown.signal())
+            .map_err(|e| anyhow!("Error binding admin listener to {}: {}", addr, e))?;
+        info!("admin listening on {}", addr);
+        servers.spawn(server);
+    }
+
+    info!(
+        "limiting request bodies to {} bytes, failing {} past the deadline",
+        limits.max_body_bytes, limits.failure_mode
+    );
+    // Detached, they only stop with the runtime.
+    tokio::spawn(kill_switch.clone().watch());
+    tokio::spawn(watchdog.run(policy.clone()));
+    let routes = routes(policy, redactor, audit, recent, kill_switch, limits);
+    if let Some((_, tls_config)) = &serving_tls {
+        for addr in addrs {
+            let incoming = tls::TlsIncoming::bind(addr, tls_config.clone()).await?;
+            let addr = incoming.local_addr();
+            let service = warp::service(routes.clone());
+            let server = Server::builder(incoming)
+                .serve(make_service_fn(move |conn: &tls::TlsConnection| {
+                    // Lets the request span link to the connection's handshake.
+                    let (service, accept_span) = (service.clone(), conn.accept_span());
+                    let service = service_fn(move |mut request: Request<Body>| {
+                        request.extensions_mut().insert(accept_span.clone());
+                        service.clone().call(request)
+                    });
+                    async move { Ok::<_, Infallible>(service) }
+                }))
+                .with_graceful_shutdown(shutdown.signal());
+            info!("listening on {}", addr);
+            servers.spawn(async move {
+                if let Err(e) = server.await {
+                    error!("listener on {} failed: {}", addr, e);
+                }
+            });
+        }
+    } else if insecure_http {
+        bind_insecure_listeners(routes.clone(), addrs, &shutdown, &mut servers)?;
+    }
+
+    if let Some((path, mode)) = unix_socket {
+        let incoming = unix::UnixIncoming::bind(&path, mode)?;
+        let service = warp::service(routes.clone());
+        let server = Server::builder(incoming)
+            .serve(make_service_fn(move |_| {
+                let service = service.clone();
+                async move { Ok::<_, Infallible>(service) }
+            }))
+            .with_graceful_shutdown(shutdown.signal());
+        info!("listening on {}", path.display());
+        servers.spawn(async move {
+            if let Err(e) = server.await {
+                error!("listener on {} failed: {}", path.display(), e);
+            }
+        });
+    }
+
+    // Report not-ready first so endpoints drop this pod before it stops
+    // accepting connections, then let in-flight requests finish.
+    let signal = shutdown::wait_for_signal().await?;
+    info!("received {}, draining for {:?}", signal, drain_period);
+    readiness.set(readyz::SHUTDOWN, Err("shutting down".to_string()));
+    // A second signal skips whatever is left of the shutdown.
+    tokio::select! {
+        drained = stop(drain_period, shutdown_timeout, &shutdown, &mut servers) => {
+            if !drained {
+                servers.abort_all();
+            }
+        }
+        signal = shutdown::wait_for_signal() => {
+            warn!("received {} again, exiting now", signal?);
+            servers.abort_all();
+            return Ok(());
+        }
+    }
+    if let Some(provider) = tracer_provider {
+        if let Err(e) = provider.shutdown() {
+            error!("failed to flush traces: {}", e);
+        }
+    }
+    info!("shutdown complete");
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use admission::AdmissionReviewResponse;
+    use policy::Policy;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpStream,
+    };
+
+    #[tokio::test]
+    async fn test_stop_timeout() {
+        let shutdown = Shutdown::new();
+        let mut servers = JoinSet::new();
+        servers.spawn(shutdown.signal());
+        assert!(
+            stop(
+                Duration::ZERO,
+                Duration::from_secs(1),
+                &shutdown,
+                &mut servers
+            )
+            .await
+        );
+
+        // A connection that never finishes is given up on.
+        let shutdown = Shutdown::new();
+        let mut servers = JoinSet::new();
+        servers.spawn(std::future::pending());
+        assert!(
+            !stop(
+                Duration::ZERO,
+                Duration::from_millis(50),
+                &shutdown,
+                &mut servers
+            )
+            .await
+        );
+    }
+
+    #[test]
+    fn test_insecure_http_refused_in_cluster() {
+        assert!(!check_insecure_http(false, Some("10.96.0.1".to_string())).unwrap());
+        assert!(check_insecure_http(true, None).unwrap());
+
+        let err = check_insecure_http(true, Some("10.96.0.1".to_string())).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("refusing to start inside a cluster"));
+    }
+
+    #[tokio::test]
+    async fn test_insecure_http_serves_mutate() {
+        let routes = routes(
+            Arc::new(SharedPolicy::new(None, Policy::default())),
+            Arc::new(Redactor::default()),
+            None,
+            Arc::new(RecentDecisions::new(0)),
+            Arc::new(KillSwitch::new(false, None, Arc::new(Readiness::new()))),
+            Arc::new(Limits::default()),
+        );
+        let shutdown = Shutdown::new();
+        let mut servers = JoinSet::new();
+        let addrs = bind_insecure_listeners(
+            routes,
+            vec!["127.0.0.1:0".parse().unwrap()],
+            &shutdown,
+            &mut servers,
+        )
+        .unwrap();
+
+        let review = include_str!("../../data/admission.json");
+        let mut stream = TcpStream::connect(addrs[0]).await.unwrap();
+        stream
+            .write_all(
+                format!(
+                    "POST /mutate HTTP/1.1\r\nhost: localhost\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+                    review.len(),
+                    review
+                )
+                .as_bytes(),
+            )
+            .await
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        let (_, body) = response.split_once("\r\n\r\n").unwrap();
+        let result: AdmissionReviewResponse = serde_json::from_str(body).unwrap();
+        let resp = result.response.expect("response missing");
+        assert_eq!(resp.uid, "0cc44252-2984-4e9d-8395-bcdd4efaa329");
+        assert!(resp.allowed);
+        assert_eq!(resp.patch_type, Some("JSONPatch".to_string()));
+
+        shutdown.trigger();
+        while servers.join_next().await.is_some() {}
+    }
+}
@@ -1,97 +1,145 @@
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use base64::prelude::*;
 use bytes::Bytes;
-use log::{debug, error, info};
-use serde::{Deserialize, Serialize};
-use serde_json::json;
+use log::{debug, error, info, log_enabled, warn, Level};
+use opentelemetry::{
+    trace::{Span, Status},
+    Context, KeyValue,
+};
+use serde_json::{json, Value};
+use std::{convert::Infallible, fmt, sync::Arc, time::Instant};
+use tokio::task::JoinError;
 use warp::Filter;
 
-#[derive(Deserialize, Debug, Clone)]
-struct AdmissionReview {
-    request: Option<AdmissionRequest>,
-}
-
-#[derive(Deserialize, Debug, Clone)]
-struct AdmissionRequest {
-    uid: String,
-    kind: Option<KindInfo>,
-    object: K8sObject,
-    #[serde(default)]
-    name: Option<String>,
-    #[serde(default)]
-    namespace: Option<String>,
-}
-
-#[derive(Deserialize, Debug, Clone)]
-struct K8sObject {
-    metadata: Metadata,
-    // include spec if needed in the future
-    // spec: Option<serde_json::Value>,
-}
-
-#[derive(Deserialize, Debug, Clone)]
-struct Metadata {
-    #[serde(default)]
-    name: Option<String>,
-    #[serde(default)]
-    #[serde(rename = "generateName")]
-    generate_name: Option<String>,
-    namespace: String,
-}
-
-#[derive(Deserialize, Debug, Clone)]
-struct KindInfo {
-    kind: String,
-}
+use super::admission::{
+    self, AdmissionRequest, AdmissionReview, AdmissionReviewResponse, Response,
+};
+use super::audit::{AuditEntry, AuditLog};
+use super::killswitch::KillSwitch;
+use super::limits::{
+    self, BodyReadError, BodyTooLarge, Deadline, DeadlineExceeded, FailureMode, Limits,
+};
+use super::metrics;
+use super::patch;
+use super::policy::{Decision, Policy, SharedPolicy, SkipReason};
+use super::recent::RecentDecisions;
+use super::redact::Redactor;
+use super::telemetry::{self, RequestContext};
 
-#[derive(Deserialize, Serialize, Debug)]
-#[serde(rename_all = "camelCase")]
-struct AdmissionReviewResponse {
-    api_version: String,
-    kind: String,
-    response: Option<Response>,
-}
-
-#[derive(Deserialize, Serialize, Debug)]
-#[serde(rename_all = "camelCase")]
-struct Response {
-    uid: String,
-    allowed: bool,
-    patch_type: Option<String>,
-    patch: Option<String>,
-}
+const SELF_TEST_REVIEW: &str = include_str!("../../data/admission.json");
 
+/// The body isn't an AdmissionReview. `uid` is the request uid, when it was
+/// found at the start of the body.
 #[derive(Debug)]
 struct JsonDeserializeError {
+    uid: String,
+    deadline: Deadline,
     message: String,
 }
 
 impl warp::reject::Reject for JsonDeserializeError {}
 
-pub fn handler() -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+pub fn handler(
+    policy: Arc<SharedPolicy>,
+    redactor: Arc<Redactor>,
+    audit: Option<Arc<AuditLog>>,
+    recent: Arc<RecentDecisions>,
+    kill_switch: Arc<KillSwitch>,
+    limits: Arc<Limits>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
     let base_path = warp::path!("mutate");
+    let (rejection_audit, rejection_recent) = (audit.clone(), recent.clone());
 
     base_path
         .and(warp::post())
-        .and(warp::body::bytes())
+        .and(telemetry::request_context())
+        .and(limits::body(limits))
+        .and(with_redactor(redactor))
         .and_then(log_and_deserialize)
+        .untuple_one()
+        .and(with_policy(policy))
+        .and(with_audit(audit))
+        .and(with_recent(recent))
+        .and(with_kill_switch(kill_switch))
         .and_then(mutate_internal)
+        .recover(move |rejection| {
+            answer_rejection(rejection, rejection_audit.clone(), rejection_recent.clone())
+        })
 }
 
-async fn log_and_deserialize(body: Bytes) -> Result<AdmissionReview, warp::Rejection> {
-    let raw_body = String::from_utf8_lossy(&body);
-    debug!("Received raw body:\n{}", raw_body);
-    serde_json::from_slice::<AdmissionReview>(&body).map_err(|err| {
-        error!("Failed to deserialize AdmissionReview: {:?}", err);
-        warp::reject::custom(JsonDeserializeError {
-            message: err.to_string(),
-        })
-    })
+/// The policy in effect when the request arrives.
+fn with_policy(
+    policy: Arc<SharedPolicy>,
+) -> impl Filter<Extract = (Arc<Policy>,), Error = Infallible> + Clone {
+    warp::any().map(move || policy.current())
 }
 
-async fn mutate_internal(review: AdmissionReview) -> Result<impl warp::Reply, warp::Rejection> {
+fn with_audit(
+    audit: Option<Arc<AuditLog>>,
+) -> impl Filter<Extract = (Option<Arc<AuditLog>>,), Error = Infallible> + Clone {
+    warp::any().map(move || audit.clone())
+}
+
+fn with_recent(
+    recent: Arc<RecentDecisions>,
+) -> impl Filter<Extract = (Arc<RecentDecisions>,), Error = Infallible> + Clone {
+    warp::any().map(move || recent.clone())
+}
+
+fn with_kill_switch(
+    kill_switch: Arc<KillSwitch>,
+) -> impl Filter<Extract = (Arc<KillSwitch>,), Error = Infallible> + Clone {
+    warp::any().map(move || kill_switch.clone())
+}
+
+fn with_redactor(
+    redactor: Arc<Redactor>,
+) -> impl Filter<Extract = (Arc<Redactor>,), Error = Infallible> + Clone {
+    warp::any().map(move || redactor.clone())
+}
+
+async fn log_and_deserialize(
+    request_cx: RequestContext,
+    deadline: Deadline,
+    body: Bytes,
+    redactor: Arc<Redactor>,
+) -> Result<(RequestContext, Deadline, AdmissionReview), warp::Rejection> {
+    request_cx.record_body_read();
+    if log_enabled!(Level::Debug) {
+        debug!("Received body:\n{}", redactor.redact(&body));
+    }
+
+    let mut span = telemetry::child_span(&request_cx.cx, "deserialize");
+    match serde_json::from_slice::<AdmissionReview>(&body) {
+        Ok(review) => Ok((request_cx, deadline, review)),
+        Err(err) => {
+            error!("Failed to deserialize AdmissionReview: {:?}", err);
+            metrics::observe_deserialize_failure();
+            span.set_status(Status::error(err.to_string()));
+            request_cx.set_error("invalid AdmissionReview");
+            Err(warp::reject::custom(JsonDeserializeError {
+                uid: limits::uid_from_prefix(&body).unwrap_or_default(),
+                deadline,
+                message: err.to_string(),
+            }))
+        }
+    }
+}
+
+async fn mutate_internal(
+    request_cx: RequestContext,
+    deadline: Deadline,
+    review: AdmissionReview,
+    policy: Arc<Policy>,
+    audit: Option<Arc<AuditLog>>,
+    recent: Arc<RecentDecisions>,
+    kill_switch: Arc<KillSwitch>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let start = Instant::now();
     let Some(request) = review.request.clone() else {
         error!("failed to decode request");
+        request_cx.set_error("AdmissionReview has no request");
+        metrics::observe_admission("", "", "", metrics::ERROR, start.elapsed());
         let error_response = json!({
             "error": "Invalid input",
             "code": 400
@@ -102,77 +150,517 @@ async fn mutate_internal(review: AdmissionReview) -> Result<impl warp::Reply, wa
         ));
     };
 
-    // Get name and namespace from the object's metadata.
-    let metadata = request.object.metadata;
-    let name = metadata.name.unwrap_or_else(|| {
-        metadata
-            .generate_name
-            .unwrap_or_else(|| "unknown".to_string())
-    });
-    let namespace = metadata.namespace;
-
-    // Prevent mutating resources in the kube-system namespace
-    if namespace == "kube-system" {
-        info!("skipping mutation for {} in kube-system namespace", name);
-        return Ok(warp::reply::with_status(
-            warp::reply::json(&AdmissionReviewResponse {
-                api_version: "admission.k8s.io/v1".to_string(),
-                kind: "AdmissionReview".to_string(),
-                response: Some(Response {
-                    uid: request.uid,
-                    allowed: true,
-                    patch_type: None,
+    let name = request.object_name();
+    let namespace = request.object_namespace();
+    let kind = request.kind_name();
+    let operation = request.operation.clone().unwrap_or_default();
+
+    request_cx.set_attribute("admission.uid", request.uid.clone());
+    request_cx.set_attribute("admission.kind", kind.clone());
+    request_cx.set_attribute("admission.namespace", namespace.clone());
+    request_cx.set_attribute("admission.operation", operation.clone());
+
+    let evaluation = match kill_switch.engaged() {
+        Some(source) => Ok(bypass(&request.uid, source)),
+        None => {
+            let (request, policy, cx) = (request.clone(), policy.clone(), request_cx.cx.clone());
+            evaluate_by(deadline, move || admit(&request, &policy, &cx)).await
+        }
+    };
+    let admission = match evaluation {
+        Ok(admission) => admission,
+        Err(failure) => {
+            error!(
+                uid = request.uid.as_str(),
+                kind = kind.as_str(),
+                namespace = namespace.as_str(),
+                name = name.as_str(),
+                operation = operation.as_str(),
+                reason = failure.reason();
+                "failed to evaluate {}/{}: {}, failing {}",
+                namespace,
+                name,
+                failure,
+                deadline.failure_mode
+            );
+            request_cx.set_error(failure.to_string());
+            metrics::observe_admission_failure(failure.reason());
+            let review = failure_review(&request.uid, &deadline, &failure);
+            let outcome = failure_decision(&review);
+            record(
+                audit.as_deref(),
+                &recent,
+                AuditEntry {
+                    timestamp: AuditEntry::now(),
+                    uid: request.uid.clone(),
+                    user: request.username(),
+                    kind: kind.clone(),
+                    namespace: namespace.clone(),
+                    name: name.clone(),
+                    operation: operation.clone(),
+                    decision: outcome.to_string(),
+                    rule: failure.reason().to_string(),
                     patch: None,
-                }),
-            }),
-            warp::http::StatusCode::OK,
-        ));
+                },
+                failure.to_string(),
+            );
+            let reply =
+                warp::reply::with_status(warp::reply::json(&review), warp::http::StatusCode::OK);
+            metrics::observe_admission(&kind, &namespace, &operation, outcome, start.elapsed());
+            return Ok(reply);
+        }
+    };
+    // `rule` is the injection rule that matched, or why the request was skipped.
+    let (outcome, rule, message) = match &admission.decision {
+        Decision::Skip(reason) => {
+            metrics::observe_skip(reason.label());
+            let message = format!("skipping mutation for {}/{}: {}", namespace, name, reason);
+            (metrics::SKIPPED, reason.label(), message)
+        }
+        Decision::Inject {
+            runtime_class_name,
+            rule,
+        } => {
+            let message = format!(
+                "mutating {}/{} with runtime class {} (rule {})",
+                namespace, name, runtime_class_name, rule
+            );
+            (metrics::INJECTED, rule.as_str(), message)
+        }
+    };
+
+    info!(
+        uid = request.uid.as_str(),
+        kind = kind.as_str(),
+        namespace = namespace.as_str(),
+        name = name.as_str(),
+        operation = operation.as_str(),
+        decision = outcome,
+        rule;
+        "{}", message
+    );
+    request_cx.set_attribute("admission.decision", outcome);
+    request_cx.set_attribute("admission.rule", rule.to_string());
+
+    record(
+        audit.as_deref(),
+        &recent,
+        AuditEntry {
+            timestamp: AuditEntry::now(),
+            uid: request.uid.clone(),
+            user: request.username(),
+            kind: kind.clone(),
+            namespace: namespace.clone(),
+            name: name.clone(),
+            operation: operation.clone(),
+            decision: outcome.to_string(),
+            rule: rule.to_string(),
+            patch: admission.patch,
+        },
+        message,
+    );
+
+    debug!(uid = request.uid.as_str(); "payload {:?}", admission.review);
+    let _span = telemetry::child_span(&request_cx.cx, "respond");
+    let reply = warp::reply::with_status(
+        warp::reply::json(&admission.review),
+        warp::http::StatusCode::OK,
+    );
+    metrics::observe_admission(&kind, &namespace, &operation, outcome, start.elapsed());
+    Ok(reply)
+}
+
+/// Writes a decision to the audit log, when there is one, and keeps it for
+/// `/decisions`.
+fn record(audit: Option<&AuditLog>, recent: &RecentDecisions, entry: AuditEntry, message: String) {
+    if let Some(audit) = audit {
+        audit.record(entry.clone());
     }
+    recent.record(entry, message);
+}
 
-    // Determine patch path based on object kind. Default to pod.
-    let patch_path = if let Some(kind_info) = &request.kind {
-        match kind_info.kind.as_str() {
-            "Deployment" | "ReplicaSet" | "StatefulSet" | "DaemonSet" => {
-                "/spec/template/spec/runtimeClassName"
-            }
-            _ => "/spec/runtimeClassName",
+/// Why a request was answered without a decision from the policy.
+#[derive(Debug, PartialEq)]
+enum Failure {
+    /// The connection failed mid-body.
+    BodyReadError(String),
+    /// The body grew past this many bytes.
+    BodyTooLarge(usize),
+    DeadlineExceeded,
+    /// The body isn't an AdmissionReview.
+    InvalidReview(String),
+    Panic(String),
+}
+
+impl Failure {
+    fn reason(&self) -> &'static str {
+        match self {
+            Failure::BodyReadError(_) => metrics::BODY_READ_ERROR,
+            Failure::BodyTooLarge(_) => metrics::BODY_TOO_LARGE,
+            Failure::DeadlineExceeded => metrics::DEADLINE_EXCEEDED,
+            Failure::InvalidReview(_) => metrics::INVALID_REVIEW,
+            Failure::Panic(_) => metrics::PANIC,
+        }
+    }
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Failure::BodyReadError(message) => write!(f, "failed to read body: {}", message),
+            Failure::BodyTooLarge(max_bytes) => write!(f, "body larger than {} bytes", max_bytes),
+            Failure::DeadlineExceeded => write!(f, "deadline exceeded"),
+            Failure::InvalidReview(message) => write!(f, "invalid AdmissionReview: {}", message),
+            Failure::Panic(message) => write!(f, "panicked: {}", message),
+        }
+    }
+}
+
+/// Runs `evaluate` on the blocking pool, so a slow evaluation can be
+/// abandoned once `deadline` passes and a panic doesn't take the connection
+/// down with it.
+async fn evaluate_by<T: Send + 'static>(
+    deadline: Deadline,
+    evaluate: impl FnOnce() -> T + Send + 'static,
+) -> Result<T, Failure> {
+    if deadline.expired() {
+        return Err(Failure::DeadlineExceeded);
+    }
+
+    match tokio::time::timeout_at(deadline.at, tokio::task::spawn_blocking(evaluate)).await {
+        Ok(Ok(value)) => Ok(value),
+        Ok(Err(e)) => Err(Failure::Panic(panic_message(e))),
+        Err(_) => Err(Failure::DeadlineExceeded),
+    }
+}
+
+fn panic_message(error: JoinError) -> String {
+    match error.try_into_panic() {
+        Ok(payload) => match payload.downcast::<String>() {
+            Ok(message) => *message,
+            Err(payload) => payload
+                .downcast_ref::<&str>()
+                .map(|message| message.to_string())
+                .unwrap_or_else(|| "unknown panic".to_string()),
+        },
+        Err(error) => error.to_string(),
+    }
+}
+
+/// The answer for a request that couldn't be evaluated, following the
+/// configured failure mode. Oversized bodies are always denied, since the
+/// object was never seen.
+fn failure_review(uid: &str, deadline: &Deadline, failure: &Failure) -> AdmissionReviewResponse {
+    let status = match failure {
+        Failure::BodyReadError(message) => admission::Status {
+            code: 400,
+            message: format!("failed to read the AdmissionReview: {}", message),
+        },
+        Failure::BodyTooLarge(max_bytes) => admission::Status {
+            code: 413,
+            message: format!("AdmissionReview is larger than {} bytes", max_bytes),
+        },
+        Failure::DeadlineExceeded => admission::Status {
+            code: 504,
+            message: format!(
+                "protect-webhook did not finish within {}",
+                humantime::format_duration(deadline.budget)
+            ),
+        },
+        // The panic message stays in the webhook's log.
+        Failure::Panic(_) => admission::Status {
+            code: 500,
+            message: "protect-webhook failed to evaluate the request".to_string(),
+        },
+        Failure::InvalidReview(message) => admission::Status {
+            code: 400,
+            message: format!("invalid AdmissionReview: {}", message),
+        },
+    };
+
+    match (failure, deadline.failure_mode) {
+        (Failure::BodyTooLarge(_), _) | (_, FailureMode::Closed) => {
+            review_response(uid, false, Some(status))
         }
+        (_, FailureMode::Open) => review_response(uid, true, None),
+    }
+}
+
+/// How a failure answer is counted: `denied` when it denies the request,
+/// `error` when it lets it through.
+fn failure_decision(review: &AdmissionReviewResponse) -> &'static str {
+    match &review.response {
+        Some(response) if !response.allowed => metrics::DENIED,
+        _ => metrics::ERROR,
+    }
+}
+
+/// Answers the rejections raised while reading the body with an
+/// AdmissionReview, so the API server reports why instead of a bare HTTP
+/// error. The uid is recovered from the part of the body that was read, and
+/// the answer is recorded like any other decision.
+async fn answer_rejection(
+    rejection: warp::Rejection,
+    audit: Option<Arc<AuditLog>>,
+    recent: Arc<RecentDecisions>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let (uid, deadline, failure) = if let Some(BodyTooLarge {
+        uid,
+        max_bytes,
+        deadline,
+    }) = rejection.find()
+    {
+        warn!(uid = uid.as_str(); "rejecting request body larger than {} bytes", max_bytes);
+        (uid, deadline, Failure::BodyTooLarge(*max_bytes))
+    } else if let Some(DeadlineExceeded { uid, deadline }) = rejection.find() {
+        warn!(
+            uid = uid.as_str();
+            "deadline of {} exceeded reading request body, failing {}",
+            humantime::format_duration(deadline.budget),
+            deadline.failure_mode
+        );
+        (uid, deadline, Failure::DeadlineExceeded)
+    } else if let Some(BodyReadError {
+        uid,
+        deadline,
+        message,
+    }) = rejection.find()
+    {
+        warn!(
+            uid = uid.as_str();
+            "failed to read request body: {}, failing {}",
+            message,
+            deadline.failure_mode
+        );
+        (uid, deadline, Failure::BodyReadError(message.clone()))
+    } else if let Some(JsonDeserializeError {
+        uid,
+        deadline,
+        message,
+    }) = rejection.find()
+    {
+        warn!(uid = uid.as_str(); "answering invalid AdmissionReview, failing {}", deadline.failure_mode);
+        (uid, deadline, Failure::InvalidReview(message.clone()))
     } else {
-        "/spec/runtimeClassName"
+        return Err(rejection);
     };
 
-    let patch_template = format!(
-        r#"[{{ "op": "add", "path": "{}", "value": "edera" }}]"#,
-        patch_path
+    metrics::observe_admission_failure(failure.reason());
+    let review = failure_review(uid, deadline, &failure);
+    let outcome = failure_decision(&review);
+    // Nothing but the uid is known about the request.
+    record(
+        audit.as_deref(),
+        &recent,
+        AuditEntry {
+            timestamp: AuditEntry::now(),
+            uid: uid.clone(),
+            user: String::new(),
+            kind: String::new(),
+            namespace: String::new(),
+            name: String::new(),
+            operation: String::new(),
+            decision: outcome.to_string(),
+            rule: failure.reason().to_string(),
+            patch: None,
+        },
+        failure.to_string(),
     );
-    let patch = BASE64_STANDARD.encode(patch_template.as_bytes());
+    metrics::observe_admission_duration(outcome, deadline.started.elapsed());
+    Ok(warp::reply::with_status(
+        warp::reply::json(&review),
+        warp::http::StatusCode::OK,
+    ))
+}
+
+fn review_response(
+    uid: &str,
+    allowed: bool,
+    status: Option<admission::Status>,
+) -> AdmissionReviewResponse {
+    AdmissionReviewResponse {
+        api_version: "admission.k8s.io/v1".to_string(),
+        kind: "AdmissionReview".to_string(),
+        response: Some(Response {
+            uid: uid.to_string(),
+            allowed,
+            patch_type: None,
+            patch: None,
+            status,
+        }),
+    }
+}
+
+/// The outcome of admitting one request.
+struct Admission {
+    decision: Decision,
+    /// The JSON Patch before it's encoded into `review`.
+    patch: Option<Value>,
+    review: AdmissionReviewResponse,
+}
+
+/// Evaluates the policy for `request` and builds the AdmissionReview answer,
+/// tracing both steps under `cx`.
+fn admit(request: &AdmissionRequest, policy: &Policy, cx: &Context) -> Admission {
+    let mut span = telemetry::child_span(cx, "evaluate_policy");
+    let decision = policy.evaluate(request);
+    span.set_attribute(KeyValue::new(
+        "admission.decision",
+        decision_label(&decision),
+    ));
+    span.end();
 
-    let response = AdmissionReviewResponse {
+    let patch = match &decision {
+        Decision::Skip(_) => None,
+        Decision::Inject {
+            runtime_class_name,
+            rule,
+        } => {
+            let _span = telemetry::child_span(cx, "build_patch");
+            Some(patch::build(request, policy, runtime_class_name, rule))
+        }
+    };
+
+    let review = AdmissionReviewResponse {
         api_version: "admission.k8s.io/v1".to_string(),
         kind: "AdmissionReview".to_string(),
         response: Some(Response {
-            uid: request.uid,
+            uid: request.uid.clone(),
             allowed: true,
-            patch_type: Some("JSONPatch".to_string()),
-            patch: Some(patch),
+            patch_type: patch.as_ref().map(|_| "JSONPatch".to_string()),
+            patch: patch
+                .as_ref()
+                .map(|patch| BASE64_STANDARD.encode(patch.to_string().as_bytes())),
+            status: None,
         }),
     };
 
-    info!("mutating {}/{}", namespace, name);
-    debug!("payload {:?}", response);
-    Ok(warp::reply::with_status(
-        warp::reply::json(&response),
-        warp::http::StatusCode::OK,
-    ))
+    Admission {
+        decision,
+        patch,
+        review,
+    }
+}
+
+/// Allows the request unmutated without evaluating the policy, while the kill
+/// switch is engaged by `source`.
+fn bypass(uid: &str, source: &'static str) -> Admission {
+    Admission {
+        decision: Decision::Skip(SkipReason::KillSwitch(source)),
+        patch: None,
+        review: review_response(uid, true, None),
+    }
+}
+
+fn decision_label(decision: &Decision) -> &'static str {
+    match decision {
+        Decision::Skip(_) => metrics::SKIPPED,
+        Decision::Inject { .. } => metrics::INJECTED,
+    }
+}
+
+/// Runs the sample AdmissionReview in `data/admission.json` through the
+/// mutator and checks that the answer is well formed.
+pub fn self_test(policy: &Policy) -> Result<()> {
+    let review: AdmissionReview = serde_json::from_str(SELF_TEST_REVIEW)
+        .map_err(|e| anyhow!("Error parsing self-test AdmissionReview: {}", e))?;
+    let request = review
+        .request
+        .ok_or_else(|| anyhow!("self-test AdmissionReview has no request"))?;
+
+    let Admission {
+        decision, review, ..
+    } = admit(&request, policy, &Context::new());
+    let response = review
+        .response
+        .ok_or_else(|| anyhow!("self-test produced no response"))?;
+
+    if response.uid != request.uid {
+        return Err(anyhow!(
+            "self-test response uid {} does not match request uid {}",
+            response.uid,
+            request.uid
+        ));
+    }
+    if !response.allowed {
+        return Err(anyhow!("self-test request was not allowed"));
+    }
+
+    match (decision, response.patch) {
+        (Decision::Skip(_), None) => Ok(()),
+        (Decision::Inject { .. }, Some(patch)) => {
+            let patch = BASE64_STANDARD
+                .decode(patch)
+                .map_err(|e| anyhow!("Error decoding self-test patch: {}", e))?;
+            match serde_json::from_slice::<serde_json::Value>(&patch) {
+                Ok(serde_json::Value::Array(ops)) if !ops.is_empty() => Ok(()),
+                Ok(_) => Err(anyhow!("self-test patch is not a list of operations")),
+                Err(e) => Err(anyhow!("Error parsing self-test patch: {}", e)),
+            }
+        }
+        (decision, _) => Err(anyhow!(
+            "self-test patch does not match decision {:?}",
+            decision
+        )),
+    }
+}
+
+/// Runs the self-test on the blocking pool under `deadline`, the way requests
+/// are evaluated, for the liveness watchdog. Only not finishing in time is an
+/// error: a failing self-test is for readiness to report, and a restart
+/// wouldn't fix it.
+pub async fn synthetic_admission(policy: Arc<Policy>, deadline: Deadline) -> Result<()> {
+    match evaluate_by(deadline, move || self_test(&policy)).await {
+        Ok(Ok(())) => Ok(()),
+        Ok(Err(e)) => {
+            debug!("synthetic admission failed its self-test: {}", e);
+            Ok(())
+        }
+        Err(Failure::Panic(message)) => {
+            debug!("synthetic admission panicked: {}", message);
+            Ok(())
+        }
+        Err(Failure::DeadlineExceeded) => Err(anyhow!(
+            "synthetic admission did not finish within {}",
+            humantime::format_duration(deadline.budget)
+        )),
+        Err(failure) => Err(anyhow!("synthetic admission failed: {}", failure)),
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use serde_json::{json, Value};
+    use crate::server::admission::{K8sObject, KindInfo, Metadata};
+    use crate::server::readyz::Readiness;
+    use serde_json::json;
+    use std::time::Duration;
     use warp::test::request;
     use warp::Reply;
 
+    fn kill_switch() -> Arc<KillSwitch> {
+        Arc::new(KillSwitch::new(false, None, Arc::new(Readiness::new())))
+    }
+
+    /// Admits `review` under `policy` and parses the answer.
+    async fn mutate(policy: Policy, review: AdmissionReview) -> AdmissionReviewResponse {
+        let response = mutate_internal(
+            RequestContext::default(),
+            Deadline::default(),
+            review,
+            Arc::new(policy),
+            None,
+            Arc::new(RecentDecisions::new(0)),
+            kill_switch(),
+        )
+        .await
+        .unwrap();
+        let body = warp::hyper::body::to_bytes(response.into_response().into_body())
+            .await
+            .unwrap();
+        serde_json::from_slice(&body).unwrap()
+    }
+
     #[tokio::test]
     async fn test_mutate_pod() {
         let admission_review = AdmissionReview {
@@ -185,19 +673,18 @@ mod tests {
                     metadata: Metadata {
                         name: Some("test-name".to_string()),
                         generate_name: None,
-                        namespace: "test-namespace".to_string(),
+                        namespace: Some("test-namespace".to_string()),
+                        ..Default::default()
                     },
+                    ..Default::default()
                 },
                 name: None,
                 namespace: None,
+                ..Default::default()
             }),
         };
 
-        let response = mutate_internal(admission_review).await.unwrap();
-        let body = warp::hyper::body::to_bytes(response.into_response().into_body())
-            .await
-            .unwrap();
-        let result: AdmissionReviewResponse = serde_json::from_slice(&body).unwrap();
+        let result = mutate(Policy::default(), admission_review).await;
 
         // Check the response structure
         assert_eq!(result.api_version, "admission.k8s.io/v1");
@@ -237,19 +724,18 @@ mod tests {
                     metadata: Metadata {
                         name: Some("rs-name".to_string()),
                         generate_name: None,
-                        namespace: "rs-namespace".to_string(),
+                        namespace: Some("rs-namespace".to_string()),
+                        ..Default::default()
                     },
+                    ..Default::default()
                 },
                 name: None,
                 namespace: None,
+                ..Default::default()
             }),
         };
 
-        let response = mutate_internal(admission_review).await.unwrap();
-        let body = warp::hyper::body::to_bytes(response.into_response().into_body())
-            .await
-            .unwrap();
-        let result: AdmissionReviewResponse = serde_json::from_slice(&body).unwrap();
+        let result = mutate(Policy::default(), admission_review).await;
 
         // Check the response structure
         assert_eq!(result.api_version, "admission.k8s.io/v1");
@@ -279,9 +765,19 @@ mod tests {
 
     #[tokio::test]
     async fn test_mutate_endpoint() {
+        let recent = Arc::new(RecentDecisions::new(10));
         let filter = warp::post()
             .and(warp::path("mutate"))
+            .and(telemetry::request_context())
+            .and(limits::deadline(Arc::new(Limits::default())))
             .and(warp::body::json())
+            .and(with_policy(Arc::new(SharedPolicy::new(
+                None,
+                Policy::default(),
+            ))))
+            .and(with_audit(None))
+            .and(with_recent(recent.clone()))
+            .and(with_kill_switch(kill_switch()))
             .and_then(mutate_internal);
 
         let admission_review = json!({
@@ -319,6 +815,11 @@ mod tests {
         assert_eq!(response.uid, "test-uid");
         assert!(response.allowed);
         assert_eq!(response.patch_type, Some("JSONPatch".to_string()));
+
+        let recorded = recent.find(Some("test-namespace"), Some("test-name"));
+        assert_eq!(recorded.len(), 1);
+        assert_eq!(recorded[0].entry.uid, "test-uid");
+        assert_eq!(recorded[0].entry.decision, metrics::INJECTED);
     }
 
     #[tokio::test]
@@ -333,19 +834,18 @@ mod tests {
                     metadata: Metadata {
                         name: Some("deployment-name".to_string()),
                         generate_name: None,
-                        namespace: "deployment-namespace".to_string(),
+                        namespace: Some("deployment-namespace".to_string()),
+                        ..Default::default()
                     },
+                    ..Default::default()
                 },
                 name: None,
                 namespace: None,
+                ..Default::default()
             }),
         };
 
-        let response = mutate_internal(admission_review).await.unwrap();
-        let body = warp::hyper::body::to_bytes(response.into_response().into_body())
-            .await
-            .unwrap();
-        let result: AdmissionReviewResponse = serde_json::from_slice(&body).unwrap();
+        let result = mutate(Policy::default(), admission_review).await;
 
         // Verify overall structure
         assert_eq!(result.api_version, "admission.k8s.io/v1");
@@ -382,19 +882,18 @@ mod tests {
                     metadata: Metadata {
                         name: Some("statefulset-name".to_string()),
                         generate_name: None,
-                        namespace: "statefulset-namespace".to_string(),
+                        namespace: Some("statefulset-namespace".to_string()),
+                        ..Default::default()
                     },
+                    ..Default::default()
                 },
                 name: None,
                 namespace: None,
+                ..Default::default()
             }),
         };
 
-        let response = mutate_internal(admission_review).await.unwrap();
-        let body = warp::hyper::body::to_bytes(response.into_response().into_body())
-            .await
-            .unwrap();
-        let result: AdmissionReviewResponse = serde_json::from_slice(&body).unwrap();
+        let result = mutate(Policy::default(), admission_review).await;
 
         // Verify overall structure
         assert_eq!(result.api_version, "admission.k8s.io/v1");
@@ -431,19 +930,18 @@ mod tests {
                     metadata: Metadata {
                         name: Some("daemonset-name".to_string()),
                         generate_name: None,
-                        namespace: "daemonset-namespace".to_string(),
+                        namespace: Some("daemonset-namespace".to_string()),
+                        ..Default::default()
                     },
+                    ..Default::default()
                 },
                 name: None,
                 namespace: None,
+                ..Default::default()
             }),
         };
 
-        let response = mutate_internal(admission_review).await.unwrap();
-        let body = warp::hyper::body::to_bytes(response.into_response().into_body())
-            .await
-            .unwrap();
-        let result: AdmissionReviewResponse = serde_json::from_slice(&body).unwrap();
+        let result = mutate(Policy::default(), admission_review).await;
 
         // Verify overall structure
         assert_eq!(result.api_version, "admission.k8s.io/v1");
@@ -480,19 +978,18 @@ mod tests {
                     metadata: Metadata {
                         name: Some("kube-workload".to_string()),
                         generate_name: None,
-                        namespace: "kube-system".to_string(),
+                        namespace: Some("kube-system".to_string()),
+                        ..Default::default()
                     },
+                    ..Default::default()
                 },
                 name: None,
                 namespace: None,
+                ..Default::default()
             }),
         };
 
-        let response = mutate_internal(admission_review).await.unwrap();
-        let body = warp::hyper::body::to_bytes(response.into_response().into_body())
-            .await
-            .unwrap();
-        let result: AdmissionReviewResponse = serde_json::from_slice(&body).unwrap();
+        let result = mutate(Policy::default(), admission_review).await;
 
         // Verify overall structure
         assert_eq!(result.api_version, "admission.k8s.io/v1");
@@ -505,4 +1002,306 @@ mod tests {
         assert_eq!(resp.patch_type, None);
         assert_eq!(resp.patch, None);
     }
+
+    #[tokio::test]
+    async fn test_mutate_gpu_pod_runtime_class() {
+        let admission_review: AdmissionReview =
+            serde_json::from_str(include_str!("../../data/admission-gpu.json")).unwrap();
+        let policy: Policy = serde_json::from_value(json!({
+            "extendedResources": [{
+                "resource": "nvidia.com/gpu",
+                "action": "runtimeClass",
+                "runtimeClassName": "edera-gpu"
+            }]
+        }))
+        .unwrap();
+
+        let result = mutate(policy, admission_review).await;
+
+        let resp = result.response.expect("response missing");
+        assert!(resp.allowed);
+
+        let patch_base64 = resp.patch.expect("patch missing");
+        let patch_json: Value =
+            serde_json::from_slice(&BASE64_STANDARD.decode(patch_base64).unwrap()).unwrap();
+
+        let expected_patch = json!([{
+            "op": "add",
+            "path": "/spec/runtimeClassName",
+            "value": "edera-gpu"
+        }]);
+
+        assert_eq!(patch_json, expected_patch);
+    }
+
+    #[tokio::test]
+    async fn test_gpu_pod_skipped() {
+        let admission_review: AdmissionReview =
+            serde_json::from_str(include_str!("../../data/admission-gpu.json")).unwrap();
+        let policy: Policy = serde_json::from_value(json!({
+            "extendedResources": [{ "resource": "nvidia.com/gpu", "action": "skip" }]
+        }))
+        .unwrap();
+
+        let result = mutate(policy, admission_review).await;
+
+        let resp = result.response.expect("response missing");
+        assert!(resp.allowed);
+        assert_eq!(resp.patch_type, None);
+        assert_eq!(resp.patch, None);
+    }
+
+    #[tokio::test]
+    async fn test_deadline_exceeded() {
+        let admission_review: AdmissionReview =
+            serde_json::from_str(include_str!("../../data/admission.json")).unwrap();
+        let uid = admission_review.request.as_ref().unwrap().uid.clone();
+        let limits = Limits {
+            timeout: Duration::ZERO,
+            failure_mode: FailureMode::Closed,
+            ..Limits::default()
+        };
+
+        let recent = Arc::new(RecentDecisions::new(10));
+
+        let response = mutate_internal(
+            RequestContext::default(),
+            limits.deadline(None),
+            admission_review,
+            Arc::new(Policy::default()),
+            None,
+            recent.clone(),
+            kill_switch(),
+        )
+        .await
+        .unwrap();
+        let body = warp::hyper::body::to_bytes(response.into_response().into_body())
+            .await
+            .unwrap();
+        let result: AdmissionReviewResponse = serde_json::from_slice(&body).unwrap();
+
+        let resp = result.response.expect("response missing");
+        assert_eq!(resp.uid, uid);
+        assert!(!resp.allowed);
+        assert_eq!(resp.patch, None);
+        assert_eq!(resp.status.unwrap().code, 504);
+
+        let recorded = recent.find(None, None);
+        assert_eq!(recorded[0].entry.decision, metrics::DENIED);
+        assert_eq!(recorded[0].entry.rule, metrics::DEADLINE_EXCEEDED);
+    }
+
+    #[tokio::test]
+    async fn test_kill_switch() {
+        let admission_review: AdmissionReview =
+            serde_json::from_str(include_str!("../../data/admission.json")).unwrap();
+        let kill_switch = kill_switch();
+        kill_switch.set(true);
+        let recent = Arc::new(RecentDecisions::new(10));
+
+        let response = mutate_internal(
+            RequestContext::default(),
+            Deadline::default(),
+            admission_review,
+            Arc::new(Policy::default()),
+            None,
+            recent.clone(),
+            kill_switch,
+        )
+        .await
+        .unwrap();
+        let body = warp::hyper::body::to_bytes(response.into_response().into_body())
+            .await
+            .unwrap();
+        let result: AdmissionReviewResponse = serde_json::from_slice(&body).unwrap();
+
+        let resp = result.response.expect("response missing");
+        assert!(resp.allowed);
+        assert_eq!(resp.patch, None);
+        assert_eq!(recent.find(None, None)[0].entry.rule, "kill_switch");
+    }
+
+    #[tokio::test]
+    async fn test_evaluation_panic() {
+        assert_eq!(evaluate_by(Deadline::default(), || 1).await, Ok(1));
+
+        let failure = evaluate_by(Deadline::default(), || -> u32 {
+            panic!("no rule for {}", "Pod")
+        })
+        .await
+        .unwrap_err();
+        assert_eq!(failure, Failure::Panic("no rule for Pod".to_string()));
+        assert_eq!(failure.reason(), metrics::PANIC);
+
+        let open = failure_review("panic-uid", &Deadline::default(), &failure);
+        assert_eq!(failure_decision(&open), metrics::ERROR);
+        let open = open.response.unwrap();
+        assert_eq!(open.uid, "panic-uid");
+        assert!(open.allowed);
+        assert_eq!(open.patch, None);
+
+        let closed = Limits {
+            failure_mode: FailureMode::Closed,
+            ..Limits::default()
+        };
+        let closed = failure_review("panic-uid", &closed.deadline(None), &failure);
+        assert_eq!(failure_decision(&closed), metrics::DENIED);
+        let closed = closed.response.unwrap();
+        assert!(!closed.allowed);
+        assert_eq!(closed.status.unwrap().code, 500);
+    }
+
+    #[tokio::test]
+    async fn test_body_too_large() {
+        let limits = Limits {
+            max_body_bytes: 256,
+            ..Limits::default()
+        };
+        let recent = Arc::new(RecentDecisions::new(10));
+        let filter = handler(
+            Arc::new(SharedPolicy::new(None, Policy::default())),
+            Arc::new(Redactor::default()),
+            None,
+            recent.clone(),
+            kill_switch(),
+            Arc::new(limits),
+        );
+
+        let response = request()
+            .method("POST")
+            .path("/mutate?timeout=10s")
+            .body(include_str!("../../data/admission.json"))
+            .reply(&filter)
+            .await;
+
+        assert_eq!(response.status(), 200);
+        let result: AdmissionReviewResponse = serde_json::from_slice(response.body()).unwrap();
+        let resp = result.response.expect("response missing");
+        assert_eq!(resp.uid, "0cc44252-2984-4e9d-8395-bcdd4efaa329");
+        assert!(!resp.allowed);
+        assert_eq!(resp.status.unwrap().code, 413);
+
+        let recorded = recent.find(None, None);
+        assert_eq!(
+            recorded[0].entry.uid,
+            "0cc44252-2984-4e9d-8395-bcdd4efaa329"
+        );
+        assert_eq!(recorded[0].entry.rule, metrics::BODY_TOO_LARGE);
+    }
+
+    #[tokio::test]
+    async fn test_invalid_review() {
+        let filter = |failure_mode| {
+            handler(
+                Arc::new(SharedPolicy::new(None, Policy::default())),
+                Arc::new(Redactor::default()),
+                None,
+                Arc::new(RecentDecisions::new(0)),
+                kill_switch(),
+                Arc::new(Limits {
+                    failure_mode,
+                    ..Limits::default()
+                }),
+            )
+        };
+        let answer = |response: warp::http::Response<Bytes>| {
+            assert_eq!(response.status(), 200);
+            let result: AdmissionReviewResponse = serde_json::from_slice(response.body()).unwrap();
+            result.response.expect("response missing")
+        };
+        let body = r#"{"request":{"uid":"bad-uid","kind":42}}"#;
+
+        let open = request()
+            .method("POST")
+            .path("/mutate")
+            .body(body)
+            .reply(&filter(FailureMode::Open))
+            .await;
+        let open = answer(open);
+        assert_eq!(open.uid, "bad-uid");
+        assert!(open.allowed);
+
+        let closed = request()
+            .method("POST")
+            .path("/mutate")
+            .body(body)
+            .reply(&filter(FailureMode::Closed))
+            .await;
+        let closed = answer(closed);
+        assert_eq!(closed.uid, "bad-uid");
+        assert!(!closed.allowed);
+        assert_eq!(closed.status.unwrap().code, 400);
+    }
+
+    #[tokio::test]
+    async fn test_body_deadline_exceeded() {
+        let deadline = Limits {
+            failure_mode: FailureMode::Closed,
+            ..Limits::default()
+        }
+        .deadline(None);
+        let rejection = warp::reject::custom(DeadlineExceeded {
+            uid: "slow-uid".to_string(),
+            deadline,
+        });
+
+        let recent = Arc::new(RecentDecisions::new(10));
+
+        let response = answer_rejection(rejection, None, recent.clone())
+            .await
+            .unwrap()
+            .into_response();
+        let body = warp::hyper::body::to_bytes(response.into_body())
+            .await
+            .unwrap();
+        let result: AdmissionReviewResponse = serde_json::from_slice(&body).unwrap();
+        let resp = result.response.expect("response missing");
+        assert_eq!(resp.uid, "slow-uid");
+        assert!(!resp.allowed);
+        assert_eq!(resp.status.unwrap().code, 504);
+
+        let recorded = recent.find(None, None);
+        assert_eq!(recorded[0].entry.uid, "slow-uid");
+        assert_eq!(recorded[0].entry.decision, metrics::DENIED);
+        assert_eq!(recorded[0].entry.rule, metrics::DEADLINE_EXCEEDED);
+    }
+
+    #[tokio::test]
+    async fn test_body_read_error() {
+        let rejection = warp::reject::custom(BodyReadError {
+            uid: "reset-uid".to_string(),
+            deadline: Deadline::default(),
+            message: "connection reset".to_string(),
+        });
+
+        let recent = Arc::new(RecentDecisions::new(10));
+
+        let response = answer_rejection(rejection, None, recent.clone())
+            .await
+            .unwrap()
+            .into_response();
+        let body = warp::hyper::body::to_bytes(response.into_body())
+            .await
+            .unwrap();
+        let result: AdmissionReviewResponse = serde_json::from_slice(&body).unwrap();
+        let resp = result.response.expect("response missing");
+        assert_eq!(resp.uid, "reset-uid");
+        assert!(resp.allowed);
+
+        let recorded = recent.find(None, None);
+        assert_eq!(recorded[0].entry.uid, "reset-uid");
+        assert_eq!(recorded[0].entry.decision, metrics::ERROR);
+        assert_eq!(recorded[0].entry.rule, metrics::BODY_READ_ERROR);
+    }
+
+    #[test]
+    fn test_self_test() {
+        assert!(self_test(&Policy::default()).is_ok());
+
+        let opt_in = Policy {
+            opt_in: true,
+            ..Policy::default()
+        };
+        assert!(self_test(&opt_in).is_ok());
+    }
 }
@@ -0,0 +1,365 @@
+use serde_json::{json, Map, Value};
+use std::collections::BTreeMap;
+
+use super::admission::{AdmissionRequest, Metadata};
+use super::policy::{
+    Policy, EDERA_ANNOTATION_PREFIX, PREVIOUS_RUNTIME_CLASS_ANNOTATION, RULE_ANNOTATION,
+};
+
+/// Labels and annotations to add to one metadata object.
+#[derive(Default)]
+struct MetadataPatch {
+    labels: Map<String, Value>,
+    annotations: Map<String, Value>,
+}
+
+/// Builds the JSON Patch applied to an object the policy decided to inject.
+pub fn build(
+    request: &AdmissionRequest,
+    policy: &Policy,
+    runtime_class_name: &str,
+    rule: &str,
+) -> Value {
+    // Determine patch path based on object kind. Default to pod.
+    let spec_path = if request.is_templated() {
+        "/spec/template/spec"
+    } else {
+        "/spec"
+    };
+
+    let mut ops = vec![json!({
+        "op": "add",
+        "path": format!("{}/runtimeClassName", spec_path),
+        "value": runtime_class_name,
+    })];
+
+    let mut object = MetadataPatch::default();
+    let mut template = MetadataPatch::default();
+
+    if policy.propagate_annotations && request.is_templated() {
+        // Copy the workload's Edera annotations so pod-level tooling sees them
+        template.annotations.extend(
+            request
+                .object
+                .metadata
+                .annotations
+                .iter()
+                .filter(|(key, _)| key.starts_with(EDERA_ANNOTATION_PREFIX))
+                .map(|(key, value)| (key.clone(), Value::String(value.clone()))),
+        );
+    }
+
+    if let Some(label) = &policy.mutation_label {
+        let value = Value::String(label.value.clone());
+        object.labels.insert(label.key.clone(), value.clone());
+        if request.is_templated() {
+            template.labels.insert(label.key.clone(), value);
+        }
+    }
+
+    if policy.record_mutation {
+        object
+            .annotations
+            .insert(RULE_ANNOTATION.to_string(), Value::String(rule.to_string()));
+        // An object admitted again already carries the injected class, and
+        // its first record is the one a rollback needs.
+        let previous = request
+            .pod_spec()
+            .and_then(|pod_spec| pod_spec.runtime_class_name.as_ref())
+            .filter(|previous| *previous != runtime_class_name)
+            .filter(|_| {
+                !request
+                    .object
+                    .metadata
+                    .annotations
+                    .contains_key(PREVIOUS_RUNTIME_CLASS_ANNOTATION)
+            });
+        if let Some(previous) = previous {
+            object.annotations.insert(
+                PREVIOUS_RUNTIME_CLASS_ANNOTATION.to_string(),
+                Value::String(previous.clone()),
+            );
+        }
+    }
+
+    ops.extend(metadata_ops(
+        "/metadata",
+        Some(&request.object.metadata),
+        object,
+    ));
+    if let Some(pod_template) = request.pod_template() {
+        ops.extend(metadata_ops(
+            "/spec/template/metadata",
+            pod_template.metadata.as_ref(),
+            template,
+        ));
+    }
+
+    Value::Array(ops)
+}
+
+/// Emits the operations adding `patch` to the metadata at `path`, skipping
+/// entries it already carries with the same value and creating the metadata
+/// or its maps when they are missing.
+fn metadata_ops(path: &str, metadata: Option<&Metadata>, patch: MetadataPatch) -> Vec<Value> {
+    let Some(metadata) = metadata else {
+        let mut value = Map::new();
+        if !patch.labels.is_empty() {
+            value.insert("labels".to_string(), Value::Object(patch.labels));
+        }
+        if !patch.annotations.is_empty() {
+            value.insert("annotations".to_string(), Value::Object(patch.annotations));
+        }
+        if value.is_empty() {
+            return Vec::new();
+        }
+        return vec![json!({ "op": "add", "path": path, "value": value })];
+    };
+
+    let labels = missing_entries(&metadata.labels, patch.labels);
+    let annotations = missing_entries(&metadata.annotations, patch.annotations);
+
+    let mut ops = map_ops(&format!("{}/labels", path), &metadata.labels, labels);
+    ops.extend(map_ops(
+        &format!("{}/annotations", path),
+        &metadata.annotations,
+        annotations,
+    ));
+    ops
+}
+
+fn missing_entries(
+    existing: &BTreeMap<String, String>,
+    entries: Map<String, Value>,
+) -> Map<String, Value> {
+    entries
+        .into_iter()
+        .filter(|(key, value)| existing.get(key).map(String::as_str) != value.as_str())
+        .collect()
+}
+
+/// Adds `entries` to the map at `path`, replacing the whole map when it is
+/// empty since a JSON Patch can't add a key to a map that doesn't exist.
+fn map_ops(
+    path: &str,
+    existing: &BTreeMap<String, String>,
+    entries: Map<String, Value>,
+) -> Vec<Value> {
+    if entries.is_empty() {
+        return Vec::new();
+    }
+
+    if existing.is_empty() {
+        return vec![json!({ "op": "add", "path": path, "value": entries })];
+    }
+
+    entries
+        .into_iter()
+        .map(|(key, value)| {
+            json!({
+                "op": "add",
+                "path": format!("{}/{}", path, escape(&key)),
+                "value": value,
+            })
+        })
+        .collect()
+}
+
+/// Escapes a key for use as a JSON Pointer reference token (RFC 6901).
+fn escape(key: &str) -> String {
+    key.replace('~', "~0").replace('/', "~1")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::policy::MutationLabel;
+
+    fn deployment(template_metadata: Value) -> AdmissionRequest {
+        serde_json::from_value(json!({
+            "uid": "deployment-uid",
+            "kind": { "kind": "Deployment" },
+            "object": {
+                "metadata": {
+                    "name": "web",
+                    "namespace": "default",
+                    "annotations": {
+                        "dev.edera/inject-runtime": "true",
+                        "deployment.kubernetes.io/revision": "1"
+                    }
+                },
+                "spec": {
+                    "template": {
+                        "metadata": template_metadata,
+                        "spec": { "containers": [] }
+                    }
+                }
+            }
+        }))
+        .unwrap()
+    }
+
+    fn propagating_policy() -> Policy {
+        Policy {
+            propagate_annotations: true,
+            ..Policy::default()
+        }
+    }
+
+    #[test]
+    fn test_propagate_into_empty_template_annotations() {
+        let request = deployment(json!({ "labels": { "app": "web" } }));
+
+        assert_eq!(
+            build(&request, &propagating_policy(), "edera", "default"),
+            json!([
+                { "op": "add", "path": "/spec/template/spec/runtimeClassName", "value": "edera" },
+                {
+                    "op": "add",
+                    "path": "/spec/template/metadata/annotations",
+                    "value": { "dev.edera/inject-runtime": "true" }
+                }
+            ])
+        );
+    }
+
+    #[test]
+    fn test_propagate_into_existing_template_annotations() {
+        let request = deployment(json!({ "annotations": { "prometheus.io/scrape": "true" } }));
+
+        assert_eq!(
+            build(&request, &propagating_policy(), "edera", "default"),
+            json!([
+                { "op": "add", "path": "/spec/template/spec/runtimeClassName", "value": "edera" },
+                {
+                    "op": "add",
+                    "path": "/spec/template/metadata/annotations/dev.edera~1inject-runtime",
+                    "value": "true"
+                }
+            ])
+        );
+    }
+
+    #[test]
+    fn test_propagate_disabled() {
+        let request = deployment(json!({ "labels": { "app": "web" } }));
+
+        assert_eq!(
+            build(&request, &Policy::default(), "edera", "default"),
+            json!([
+                { "op": "add", "path": "/spec/template/spec/runtimeClassName", "value": "edera" }
+            ])
+        );
+    }
+
+    fn stamping_policy() -> Policy {
+        Policy {
+            mutation_label: Some(MutationLabel {
+                key: "edera.dev/isolated".to_string(),
+                value: "true".to_string(),
+            }),
+            record_mutation: true,
+            ..Policy::default()
+        }
+    }
+
+    #[test]
+    fn test_stamp_pod_with_previous_runtime_class() {
+        let request: AdmissionRequest = serde_json::from_value(json!({
+            "uid": "pod-uid",
+            "kind": { "kind": "Pod" },
+            "object": {
+                "metadata": { "name": "web", "namespace": "default", "labels": { "app": "web" } },
+                "spec": { "runtimeClassName": "runc", "containers": [] }
+            }
+        }))
+        .unwrap();
+
+        assert_eq!(
+            build(&request, &stamping_policy(), "edera", "default"),
+            json!([
+                { "op": "add", "path": "/spec/runtimeClassName", "value": "edera" },
+                { "op": "add", "path": "/metadata/labels/edera.dev~1isolated", "value": "true" },
+                {
+                    "op": "add",
+                    "path": "/metadata/annotations",
+                    "value": {
+                        "dev.edera/previous-runtime-class": "runc",
+                        "dev.edera/rule": "default"
+                    }
+                }
+            ])
+        );
+    }
+
+    #[test]
+    fn test_stamp_readmitted_pod_keeps_previous_runtime_class() {
+        let request: AdmissionRequest = serde_json::from_value(json!({
+            "uid": "pod-uid",
+            "kind": { "kind": "Pod" },
+            "operation": "UPDATE",
+            "object": {
+                "metadata": {
+                    "name": "web",
+                    "namespace": "default",
+                    "annotations": {
+                        "dev.edera/previous-runtime-class": "runc",
+                        "dev.edera/rule": "default"
+                    }
+                },
+                "spec": { "runtimeClassName": "edera", "containers": [] }
+            }
+        }))
+        .unwrap();
+
+        assert_eq!(
+            build(&request, &stamping_policy(), "edera", "default"),
+            json!([
+                { "op": "add", "path": "/spec/runtimeClassName", "value": "edera" },
+                {
+                    "op": "add",
+                    "path": "/metadata/labels",
+                    "value": { "edera.dev/isolated": "true" }
+                }
+            ])
+        );
+
+        // A class set since the first admission doesn't replace the record either.
+        let mut request = request;
+        request.object.spec.as_mut().unwrap().pod.runtime_class_name = Some("kata".to_string());
+        let patch = build(&request, &stamping_policy(), "edera", "default");
+        assert!(!patch.to_string().contains("previous-runtime-class"));
+    }
+
+    #[test]
+    fn test_stamp_deployment_template() {
+        let request = deployment(json!({ "labels": { "app": "web" } }));
+
+        assert_eq!(
+            build(
+                &request,
+                &stamping_policy(),
+                "edera",
+                "extendedResource:nvidia.com/gpu"
+            ),
+            json!([
+                { "op": "add", "path": "/spec/template/spec/runtimeClassName", "value": "edera" },
+                {
+                    "op": "add",
+                    "path": "/metadata/labels",
+                    "value": { "edera.dev/isolated": "true" }
+                },
+                {
+                    "op": "add",
+                    "path": "/metadata/annotations/dev.edera~1rule",
+                    "value": "extendedResource:nvidia.com/gpu"
+                },
+                {
+                    "op": "add",
+                    "path": "/spec/template/metadata/labels/edera.dev~1isolated",
+                    "value": "true"
+                }
+            ])
+        );
+    }
+}
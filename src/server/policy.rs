@@ -0,0 +1,897 @@
+use anyhow::{anyhow, Result};
+use ring::digest;
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt, fs,
+    sync::{Arc, RwLock},
+    time::SystemTime,
+};
+
+use super::admission::{AdmissionRequest, OwnerReference, PodSpec};
+
+/// Annotation opting a workload into (`"true"`) or out of (`"false"`) injection.
+pub const INJECT_ANNOTATION: &str = "dev.edera/inject-runtime";
+
+/// Prefix of the Edera annotations copied into pod templates.
+pub const EDERA_ANNOTATION_PREFIX: &str = "dev.edera/";
+
+/// Annotation recording the policy rule that mutated an object.
+pub const RULE_ANNOTATION: &str = "dev.edera/rule";
+
+/// Annotation recording the runtime class an object had before it was mutated.
+pub const PREVIOUS_RUNTIME_CLASS_ANNOTATION: &str = "dev.edera/previous-runtime-class";
+
+/// Annotation exempting a workload from mutation until the RFC 3339 timestamp
+/// it's set to, e.g. `"2026-10-20T18:00:00Z"`.
+pub const BREAK_GLASS_ANNOTATION: &str = "dev.edera/break-glass";
+
+/// Annotation the kubelet sets on the API server's mirror of a static pod.
+const MIRROR_POD_ANNOTATION: &str = "kubernetes.io/config.mirror";
+
+/// Mutation policy, loaded from the JSON file named by `WEBHOOK_POLICY_FILE`.
+/// Every field is optional and defaults to the webhook's built-in behavior.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase", default, deny_unknown_fields)]
+pub struct Policy {
+    /// RuntimeClass injected when no more specific rule matches.
+    pub runtime_class_name: String,
+    /// Rules for pods requesting extended resources such as `nvidia.com/gpu`.
+    /// The first rule matching any container's requests or limits wins.
+    pub extended_resources: Vec<ExtendedResourceRule>,
+    /// Node labels with no Edera nodes behind them. Pods whose nodeSelector or
+    /// required node affinity pins them to these labels are skipped.
+    pub unsupported_node_labels: Vec<NodeLabelRule>,
+    /// Rules matching on the object's `ownerReferences`.
+    pub owners: OwnerPolicy,
+    /// Only mutate objects annotated with `dev.edera/inject-runtime: "true"`.
+    pub opt_in: bool,
+    /// Copy `dev.edera/` annotations from a workload's metadata into its pod
+    /// template so pod-level tooling sees them.
+    pub propagate_annotations: bool,
+    /// Label stamped onto mutated objects and their pod templates, so other
+    /// tooling can select isolated workloads.
+    pub mutation_label: Option<MutationLabel>,
+    /// Annotate mutated objects with the rule that fired and the runtime class
+    /// they had before, which is enough to roll the mutation back.
+    pub record_mutation: bool,
+}
+
+impl Default for Policy {
+    fn default() -> Self {
+        Policy {
+            runtime_class_name: "edera".to_string(),
+            extended_resources: Vec::new(),
+            unsupported_node_labels: Vec::new(),
+            owners: OwnerPolicy::default(),
+            opt_in: false,
+            propagate_annotations: false,
+            mutation_label: None,
+            record_mutation: false,
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct MutationLabel {
+    /// Label key, e.g. `edera.dev/isolated`.
+    pub key: String,
+    /// Label value, e.g. `true`.
+    pub value: String,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct NodeLabelRule {
+    /// Node label key, e.g. `kubernetes.io/arch`.
+    pub key: String,
+    /// Label values, e.g. `arm64`, that no Edera node carries.
+    pub values: Vec<String>,
+}
+
+impl NodeLabelRule {
+    fn matches(&self, value: &str) -> bool {
+        self.values.iter().any(|v| v == value)
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct OwnerPolicy {
+    /// When non-empty, only objects with a matching owner are mutated.
+    pub include: Vec<OwnerSelector>,
+    /// Objects with a matching owner are never mutated.
+    pub exclude: Vec<OwnerSelector>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct OwnerSelector {
+    /// Owner kind, e.g. `Job` or `DaemonSet`.
+    pub kind: String,
+    /// Owner name. A trailing `*` matches by prefix, and omitting it matches any name.
+    #[serde(default)]
+    pub name: Option<String>,
+}
+
+impl OwnerSelector {
+    fn matches(&self, owner: &OwnerReference) -> bool {
+        if owner.kind != self.kind {
+            return false;
+        }
+
+        let Some(name) = &self.name else {
+            return true;
+        };
+
+        match name.strip_suffix('*') {
+            Some(prefix) => owner.name.starts_with(prefix),
+            None => owner.name == *name,
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ExtendedResourceRule {
+    /// Resource name, e.g. `nvidia.com/gpu`. A trailing `*` matches by prefix.
+    pub resource: String,
+    #[serde(flatten)]
+    pub action: ExtendedResourceAction,
+}
+
+impl ExtendedResourceRule {
+    fn matches(&self, resource: &str) -> bool {
+        match self.resource.strip_suffix('*') {
+            Some(prefix) => resource.starts_with(prefix),
+            None => resource == self.resource,
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(
+    tag = "action",
+    rename_all = "camelCase",
+    rename_all_fields = "camelCase",
+    deny_unknown_fields
+)]
+pub enum ExtendedResourceAction {
+    RuntimeClass { runtime_class_name: String },
+    // A struct variant, so stray fields next to `"action": "skip"` are
+    // rejected too.
+    Skip {},
+}
+
+/// Outcome of evaluating a request against the policy.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Decision {
+    Inject {
+        runtime_class_name: String,
+        rule: String,
+    },
+    Skip(SkipReason),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SkipReason {
+    ExcludedNamespace(String),
+    OptedOut,
+    NotOptedIn,
+    MirrorPod,
+    WindowsOs,
+    UnsupportedNodeLabel {
+        key: String,
+        value: String,
+    },
+    ExcludedOwner {
+        kind: String,
+        name: String,
+    },
+    OwnerNotIncluded,
+    ExtendedResource(String),
+    BreakGlass {
+        until: String,
+    },
+    /// Mutation is stopped, by what is named.
+    KillSwitch(&'static str),
+}
+
+impl SkipReason {
+    /// Stable identifier used as a metrics label.
+    pub fn label(&self) -> &'static str {
+        match self {
+            SkipReason::ExcludedNamespace(_) => "excluded_namespace",
+            SkipReason::OptedOut => "opted_out",
+            SkipReason::NotOptedIn => "not_opted_in",
+            SkipReason::MirrorPod => "mirror_pod",
+            SkipReason::WindowsOs => "windows_os",
+            SkipReason::UnsupportedNodeLabel { .. } => "unsupported_node_label",
+            SkipReason::ExcludedOwner { .. } => "excluded_owner",
+            SkipReason::OwnerNotIncluded => "owner_not_included",
+            SkipReason::ExtendedResource(_) => "extended_resource",
+            SkipReason::BreakGlass { .. } => "break_glass",
+            SkipReason::KillSwitch(_) => "kill_switch",
+        }
+    }
+}
+
+impl fmt::Display for SkipReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SkipReason::ExcludedNamespace(namespace) => {
+                write!(f, "namespace {} is excluded", namespace)
+            }
+            SkipReason::OptedOut => write!(f, "{} is set to false", INJECT_ANNOTATION),
+            SkipReason::NotOptedIn => write!(f, "{} is not set to true", INJECT_ANNOTATION),
+            SkipReason::MirrorPod => write!(f, "mirror pods can not be mutated"),
+            SkipReason::WindowsOs => write!(f, "windows pods are not supported"),
+            SkipReason::UnsupportedNodeLabel { key, value } => {
+                write!(
+                    f,
+                    "pinned to nodes with {}={} which has no Edera nodes",
+                    key, value
+                )
+            }
+            SkipReason::ExcludedOwner { kind, name } => {
+                write!(f, "owner {}/{} is excluded", kind, name)
+            }
+            SkipReason::OwnerNotIncluded => write!(f, "no owner matches the include rules"),
+            SkipReason::ExtendedResource(resource) => {
+                write!(f, "extended resource {} is configured to skip", resource)
+            }
+            SkipReason::BreakGlass { until } => {
+                write!(f, "{} exempts it until {}", BREAK_GLASS_ANNOTATION, until)
+            }
+            SkipReason::KillSwitch(source) => write!(f, "kill switch engaged by {}", source),
+        }
+    }
+}
+
+/// Whether a break-glass expiry is still in the future at `now`. Expiries that
+/// don't parse never exempt anything.
+fn break_glass_active(until: &str, now: SystemTime) -> bool {
+    humantime::parse_rfc3339(until).is_ok_and(|until| until > now)
+}
+
+impl Policy {
+    pub fn load(path: &str) -> Result<Policy> {
+        let contents = match fs::read(path) {
+            Err(e) => return Err(anyhow!("Error reading policy {}: {}", path, e)),
+            Ok(contents) => contents,
+        };
+
+        serde_json::from_slice(&contents)
+            .map_err(|e| anyhow!("Error parsing policy {}: {}", path, e))
+    }
+
+    /// SHA-256 of the policy's JSON form, e.g. `sha256:9f86d0...`. Files that
+    /// only differ in formatting or spelled-out defaults hash the same.
+    pub fn hash(&self) -> String {
+        let json = serde_json::to_vec(self).expect("policy serializes to JSON");
+        let hex: String = digest::digest(&digest::SHA256, &json)
+            .as_ref()
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect();
+        format!("sha256:{}", hex)
+    }
+
+    pub fn evaluate(&self, request: &AdmissionRequest) -> Decision {
+        // Prevent mutating resources in the kube-system namespace
+        let namespace = request.object_namespace();
+        if namespace == "kube-system" {
+            return Decision::Skip(SkipReason::ExcludedNamespace(namespace));
+        }
+
+        if let Some(until) = request.annotation(BREAK_GLASS_ANNOTATION) {
+            if break_glass_active(until, SystemTime::now()) {
+                return Decision::Skip(SkipReason::BreakGlass {
+                    until: until.to_string(),
+                });
+            }
+        }
+
+        // Workload-level annotations take precedence over the pod template's
+        match request.annotation(INJECT_ANNOTATION) {
+            Some("false") => return Decision::Skip(SkipReason::OptedOut),
+            Some("true") => {}
+            _ if self.opt_in => return Decision::Skip(SkipReason::NotOptedIn),
+            _ => {}
+        }
+
+        // Mirror pods are read-only reflections of static pods
+        if request
+            .object
+            .metadata
+            .annotations
+            .contains_key(MIRROR_POD_ANNOTATION)
+        {
+            return Decision::Skip(SkipReason::MirrorPod);
+        }
+
+        if let Some(reason) = self.evaluate_owners(request) {
+            return Decision::Skip(reason);
+        }
+
+        if let Some(reason) = request
+            .pod_spec()
+            .and_then(|pod_spec| self.evaluate_placement(pod_spec))
+        {
+            return Decision::Skip(reason);
+        }
+
+        if let Some(decision) = self.evaluate_extended_resources(request) {
+            return decision;
+        }
+
+        Decision::Inject {
+            runtime_class_name: self.runtime_class_name.clone(),
+            rule: "default".to_string(),
+        }
+    }
+
+    fn evaluate_owners(&self, request: &AdmissionRequest) -> Option<SkipReason> {
+        let owners = &request.object.metadata.owner_references;
+
+        let excluded = owners
+            .iter()
+            .find(|owner| self.owners.exclude.iter().any(|rule| rule.matches(owner)));
+        if let Some(owner) = excluded {
+            return Some(SkipReason::ExcludedOwner {
+                kind: owner.kind.clone(),
+                name: owner.name.clone(),
+            });
+        }
+
+        let included = owners
+            .iter()
+            .any(|owner| self.owners.include.iter().any(|rule| rule.matches(owner)));
+        if !self.owners.include.is_empty() && !included {
+            return Some(SkipReason::OwnerNotIncluded);
+        }
+
+        None
+    }
+
+    fn evaluate_placement(&self, pod_spec: &PodSpec) -> Option<SkipReason> {
+        let os = pod_spec.os.as_ref().map(|os| os.name.as_str()).or_else(|| {
+            pod_spec
+                .node_selector
+                .get("kubernetes.io/os")
+                .map(String::as_str)
+        });
+        if os == Some("windows") {
+            return Some(SkipReason::WindowsOs);
+        }
+
+        for rule in &self.unsupported_node_labels {
+            if let Some(value) = pod_spec.node_selector.get(&rule.key) {
+                if rule.matches(value) {
+                    return Some(SkipReason::UnsupportedNodeLabel {
+                        key: rule.key.clone(),
+                        value: value.clone(),
+                    });
+                }
+            }
+
+            // Terms are ORed, so the pod is only pinned if every term restricts
+            // the key to unsupported values.
+            let pinned: Option<Vec<String>> = pod_spec
+                .required_node_selector_terms()
+                .iter()
+                .map(|term| {
+                    term.match_expressions
+                        .iter()
+                        .find(|expr| {
+                            expr.key == rule.key
+                                && expr.operator == "In"
+                                && !expr.values.is_empty()
+                                && expr.values.iter().all(|v| rule.matches(v))
+                        })
+                        .map(|expr| expr.values.join(","))
+                })
+                .collect();
+            if let Some(values) = pinned.filter(|values| !values.is_empty()) {
+                return Some(SkipReason::UnsupportedNodeLabel {
+                    key: rule.key.clone(),
+                    value: values.join(","),
+                });
+            }
+        }
+
+        None
+    }
+
+    fn evaluate_extended_resources(&self, request: &AdmissionRequest) -> Option<Decision> {
+        let pod_spec = request.pod_spec()?;
+        let requested: Vec<&str> = pod_spec
+            .all_containers()
+            .flat_map(|container| container.resource_names())
+            .collect();
+
+        self.extended_resources.iter().find_map(|rule| {
+            let resource = requested.iter().find(|resource| rule.matches(resource))?;
+            Some(match &rule.action {
+                ExtendedResourceAction::RuntimeClass { runtime_class_name } => Decision::Inject {
+                    runtime_class_name: runtime_class_name.clone(),
+                    rule: format!("extendedResource:{}", rule.resource),
+                },
+                ExtendedResourceAction::Skip {} => {
+                    Decision::Skip(SkipReason::ExtendedResource(resource.to_string()))
+                }
+            })
+        })
+    }
+}
+
+/// The policy in effect. Replacing it affects requests that arrive afterwards,
+/// while those already being evaluated finish with the old one.
+pub struct SharedPolicy {
+    path: Option<String>,
+    current: RwLock<Arc<Policy>>,
+}
+
+impl SharedPolicy {
+    /// `path` is the file `policy` was loaded from, if any.
+    pub fn new(path: Option<String>, policy: Policy) -> SharedPolicy {
+        SharedPolicy {
+            path,
+            current: RwLock::new(Arc::new(policy)),
+        }
+    }
+
+    pub fn path(&self) -> Option<&str> {
+        self.path.as_deref()
+    }
+
+    pub fn current(&self) -> Arc<Policy> {
+        self.current.read().unwrap().clone()
+    }
+
+    /// Reads the policy file again, or returns the default policy when there
+    /// is no file. The policy in effect is unchanged.
+    pub fn read(&self) -> Result<Policy> {
+        match &self.path {
+            Some(path) => Policy::load(path),
+            None => Ok(Policy::default()),
+        }
+    }
+
+    pub fn replace(&self, policy: Policy) {
+        *self.current.write().unwrap() = Arc::new(policy);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::admission::AdmissionReview;
+
+    const GPU_POD: &str = include_str!("../../data/admission-gpu.json");
+    const POD: &str = include_str!("../../data/admission.json");
+
+    fn request(fixture: &str) -> AdmissionRequest {
+        let review: AdmissionReview = serde_json::from_str(fixture).unwrap();
+        review.request.unwrap()
+    }
+
+    fn gpu_policy(action: ExtendedResourceAction) -> Policy {
+        Policy {
+            extended_resources: vec![ExtendedResourceRule {
+                resource: "nvidia.com/gpu".to_string(),
+                action,
+            }],
+            ..Policy::default()
+        }
+    }
+
+    #[test]
+    fn test_parse_policy() {
+        let policy: Policy = serde_json::from_str(
+            r#"{
+                "extendedResources": [
+                    { "resource": "nvidia.com/gpu", "action": "runtimeClass", "runtimeClassName": "edera-gpu" },
+                    { "resource": "amd.com/*", "action": "skip" }
+                ]
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(policy.runtime_class_name, "edera");
+        assert_eq!(
+            policy.extended_resources,
+            vec![
+                ExtendedResourceRule {
+                    resource: "nvidia.com/gpu".to_string(),
+                    action: ExtendedResourceAction::RuntimeClass {
+                        runtime_class_name: "edera-gpu".to_string(),
+                    },
+                },
+                ExtendedResourceRule {
+                    resource: "amd.com/*".to_string(),
+                    action: ExtendedResourceAction::Skip {},
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_unknown_policy_fields() {
+        for policy in [
+            r#"{ "extendedResource": [] }"#,
+            r#"{ "optin": true }"#,
+            r#"{ "owners": { "exclude": [{ "kind": "Job", "nme": "x" }] } }"#,
+            r#"{ "extendedResources": [{ "resource": "amd.com/*", "action": "skip", "runtimeClass": "x" }] }"#,
+            r#"{ "extendedResources": [{ "resource": "nvidia.com/gpu", "action": "runtimeClass", "runtimeclassname": "x" }] }"#,
+        ] {
+            let err = serde_json::from_str::<Policy>(policy).unwrap_err();
+            assert!(err.to_string().contains("unknown field"), "{}", err);
+        }
+    }
+
+    #[test]
+    fn test_gpu_pod_runtime_class() {
+        let policy = gpu_policy(ExtendedResourceAction::RuntimeClass {
+            runtime_class_name: "edera-gpu".to_string(),
+        });
+
+        assert_eq!(
+            policy.evaluate(&request(GPU_POD)),
+            Decision::Inject {
+                runtime_class_name: "edera-gpu".to_string(),
+                rule: "extendedResource:nvidia.com/gpu".to_string(),
+            }
+        );
+    }
+
+    #[test]
+    fn test_gpu_pod_skip() {
+        let policy = gpu_policy(ExtendedResourceAction::Skip {});
+
+        assert_eq!(
+            policy.evaluate(&request(GPU_POD)),
+            Decision::Skip(SkipReason::ExtendedResource("nvidia.com/gpu".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_gpu_prefix_match() {
+        let policy = Policy {
+            extended_resources: vec![ExtendedResourceRule {
+                resource: "nvidia.com/*".to_string(),
+                action: ExtendedResourceAction::Skip {},
+            }],
+            ..Policy::default()
+        };
+
+        assert_eq!(
+            policy.evaluate(&request(GPU_POD)),
+            Decision::Skip(SkipReason::ExtendedResource("nvidia.com/gpu".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_non_gpu_pod_default() {
+        let policy = gpu_policy(ExtendedResourceAction::Skip {});
+
+        assert_eq!(
+            policy.evaluate(&request(POD)),
+            Decision::Inject {
+                runtime_class_name: "edera".to_string(),
+                rule: "default".to_string(),
+            }
+        );
+    }
+
+    #[test]
+    fn test_zero_quantity_ignored() {
+        let policy = gpu_policy(ExtendedResourceAction::Skip {});
+        let fixture = GPU_POD.replace(r#""nvidia.com/gpu": "1""#, r#""nvidia.com/gpu": "0""#);
+
+        assert_eq!(
+            policy.evaluate(&request(&fixture)),
+            Decision::Inject {
+                runtime_class_name: "edera".to_string(),
+                rule: "default".to_string(),
+            }
+        );
+    }
+
+    fn pod(object: serde_json::Value) -> AdmissionRequest {
+        serde_json::from_value(serde_json::json!({
+            "uid": "test-uid",
+            "kind": { "kind": "Pod" },
+            "object": object,
+        }))
+        .unwrap()
+    }
+
+    fn arm64_policy() -> Policy {
+        Policy {
+            unsupported_node_labels: vec![NodeLabelRule {
+                key: "kubernetes.io/arch".to_string(),
+                values: vec!["arm64".to_string()],
+            }],
+            ..Policy::default()
+        }
+    }
+
+    #[test]
+    fn test_windows_pod_skipped() {
+        let request = pod(serde_json::json!({
+            "metadata": { "name": "win", "namespace": "default" },
+            "spec": { "os": { "name": "windows" }, "containers": [] }
+        }));
+
+        assert_eq!(
+            Policy::default().evaluate(&request),
+            Decision::Skip(SkipReason::WindowsOs)
+        );
+    }
+
+    #[test]
+    fn test_windows_node_selector_skipped() {
+        let request = pod(serde_json::json!({
+            "metadata": { "name": "win", "namespace": "default" },
+            "spec": { "nodeSelector": { "kubernetes.io/os": "windows" } }
+        }));
+
+        assert_eq!(
+            Policy::default().evaluate(&request),
+            Decision::Skip(SkipReason::WindowsOs)
+        );
+    }
+
+    #[test]
+    fn test_mirror_pod_skipped() {
+        let request = pod(serde_json::json!({
+            "metadata": {
+                "name": "etcd-node",
+                "namespace": "default",
+                "annotations": { "kubernetes.io/config.mirror": "d41d8cd98f00b204e9800998ecf8427e" }
+            }
+        }));
+
+        assert_eq!(
+            Policy::default().evaluate(&request),
+            Decision::Skip(SkipReason::MirrorPod)
+        );
+    }
+
+    #[test]
+    fn test_unsupported_node_selector_skipped() {
+        let request = pod(serde_json::json!({
+            "metadata": { "name": "arm", "namespace": "default" },
+            "spec": { "nodeSelector": { "kubernetes.io/arch": "arm64" } }
+        }));
+
+        assert_eq!(
+            arm64_policy().evaluate(&request),
+            Decision::Skip(SkipReason::UnsupportedNodeLabel {
+                key: "kubernetes.io/arch".to_string(),
+                value: "arm64".to_string(),
+            })
+        );
+    }
+
+    #[test]
+    fn test_unsupported_node_affinity_skipped() {
+        let request = pod(serde_json::json!({
+            "metadata": { "name": "arm", "namespace": "default" },
+            "spec": {
+                "affinity": { "nodeAffinity": { "requiredDuringSchedulingIgnoredDuringExecution": {
+                    "nodeSelectorTerms": [{ "matchExpressions": [
+                        { "key": "kubernetes.io/arch", "operator": "In", "values": ["arm64"] }
+                    ]}]
+                }}}
+            }
+        }));
+
+        assert_eq!(
+            arm64_policy().evaluate(&request),
+            Decision::Skip(SkipReason::UnsupportedNodeLabel {
+                key: "kubernetes.io/arch".to_string(),
+                value: "arm64".to_string(),
+            })
+        );
+    }
+
+    #[test]
+    fn test_node_affinity_with_supported_term_injected() {
+        let request = pod(serde_json::json!({
+            "metadata": { "name": "multiarch", "namespace": "default" },
+            "spec": {
+                "affinity": { "nodeAffinity": { "requiredDuringSchedulingIgnoredDuringExecution": {
+                    "nodeSelectorTerms": [
+                        { "matchExpressions": [
+                            { "key": "kubernetes.io/arch", "operator": "In", "values": ["arm64"] }
+                        ]},
+                        { "matchExpressions": [
+                            { "key": "kubernetes.io/arch", "operator": "In", "values": ["amd64"] }
+                        ]}
+                    ]
+                }}}
+            }
+        }));
+
+        assert_eq!(
+            arm64_policy().evaluate(&request),
+            Decision::Inject {
+                runtime_class_name: "edera".to_string(),
+                rule: "default".to_string(),
+            }
+        );
+    }
+
+    fn owned_pod(kind: &str, name: &str) -> AdmissionRequest {
+        pod(serde_json::json!({
+            "metadata": {
+                "name": format!("{}-abcde", name),
+                "namespace": "default",
+                "ownerReferences": [{
+                    "apiVersion": "batch/v1",
+                    "kind": kind,
+                    "name": name,
+                    "uid": "b5c2a7e4-1f3d-4a8b-9e6c-0d2f4b6a8c1e",
+                    "controller": true
+                }]
+            }
+        }))
+    }
+
+    #[test]
+    fn test_excluded_owner_skipped() {
+        let policy: Policy = serde_json::from_value(serde_json::json!({
+            "owners": { "exclude": [{ "kind": "DaemonSet" }] }
+        }))
+        .unwrap();
+
+        assert_eq!(
+            policy.evaluate(&owned_pod("DaemonSet", "node-exporter")),
+            Decision::Skip(SkipReason::ExcludedOwner {
+                kind: "DaemonSet".to_string(),
+                name: "node-exporter".to_string(),
+            })
+        );
+        assert!(matches!(
+            policy.evaluate(&owned_pod("Job", "backup")),
+            Decision::Inject { .. }
+        ));
+    }
+
+    #[test]
+    fn test_owner_include_only() {
+        let policy: Policy = serde_json::from_value(serde_json::json!({
+            "owners": { "include": [{ "kind": "Job", "name": "batch-*" }] }
+        }))
+        .unwrap();
+
+        assert!(matches!(
+            policy.evaluate(&owned_pod("Job", "batch-nightly")),
+            Decision::Inject { .. }
+        ));
+        assert_eq!(
+            policy.evaluate(&owned_pod("Job", "backup")),
+            Decision::Skip(SkipReason::OwnerNotIncluded)
+        );
+        assert_eq!(
+            policy.evaluate(&request(POD)),
+            Decision::Skip(SkipReason::OwnerNotIncluded)
+        );
+    }
+
+    fn annotated_deployment(
+        annotations: serde_json::Value,
+        template_annotations: serde_json::Value,
+    ) -> AdmissionRequest {
+        serde_json::from_value(serde_json::json!({
+            "uid": "deployment-uid",
+            "kind": { "kind": "Deployment" },
+            "object": {
+                "metadata": { "name": "web", "namespace": "default", "annotations": annotations },
+                "spec": {
+                    "template": {
+                        "metadata": { "annotations": template_annotations },
+                        "spec": { "containers": [] }
+                    }
+                }
+            }
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_workload_annotation_opt_out() {
+        let request = annotated_deployment(
+            serde_json::json!({ "dev.edera/inject-runtime": "false" }),
+            serde_json::json!({ "dev.edera/inject-runtime": "true" }),
+        );
+
+        assert_eq!(
+            Policy::default().evaluate(&request),
+            Decision::Skip(SkipReason::OptedOut)
+        );
+    }
+
+    #[test]
+    fn test_opt_in_required() {
+        let policy = Policy {
+            opt_in: true,
+            ..Policy::default()
+        };
+
+        let workload = annotated_deployment(
+            serde_json::json!({ "dev.edera/inject-runtime": "true" }),
+            serde_json::json!({}),
+        );
+        assert!(matches!(
+            policy.evaluate(&workload),
+            Decision::Inject { .. }
+        ));
+
+        let template = annotated_deployment(
+            serde_json::json!({}),
+            serde_json::json!({ "dev.edera/inject-runtime": "true" }),
+        );
+        assert!(matches!(
+            policy.evaluate(&template),
+            Decision::Inject { .. }
+        ));
+
+        let unannotated = annotated_deployment(serde_json::json!({}), serde_json::json!({}));
+        assert_eq!(
+            policy.evaluate(&unannotated),
+            Decision::Skip(SkipReason::NotOptedIn)
+        );
+    }
+
+    #[test]
+    fn test_break_glass() {
+        let request = annotated_deployment(
+            serde_json::json!({ "dev.edera/break-glass": "2999-01-01T00:00:00Z" }),
+            serde_json::json!({}),
+        );
+        assert_eq!(
+            Policy::default().evaluate(&request),
+            Decision::Skip(SkipReason::BreakGlass {
+                until: "2999-01-01T00:00:00Z".to_string()
+            })
+        );
+
+        let expired = annotated_deployment(
+            serde_json::json!({}),
+            serde_json::json!({ "dev.edera/break-glass": "2020-01-01T00:00:00Z" }),
+        );
+        assert!(matches!(
+            Policy::default().evaluate(&expired),
+            Decision::Inject { .. }
+        ));
+
+        let now = humantime::parse_rfc3339("2026-10-19T12:00:00Z").unwrap();
+        assert!(break_glass_active("2026-10-19T12:00:01Z", now));
+        assert!(!break_glass_active("2026-10-19T12:00:00Z", now));
+        assert!(!break_glass_active("tomorrow", now));
+    }
+
+    #[test]
+    fn test_policy_hash() {
+        let spelled_out: Policy =
+            serde_json::from_str(r#"{ "runtimeClassName": "edera", "optIn": false }"#).unwrap();
+        let hash = Policy::default().hash();
+        assert_eq!(hash, spelled_out.hash());
+        assert!(hash.starts_with("sha256:"));
+        assert_eq!(hash.len(), "sha256:".len() + 64);
+
+        let opt_in = Policy {
+            opt_in: true,
+            ..Policy::default()
+        };
+        assert_ne!(hash, opt_in.hash());
+
+        let shared = SharedPolicy::new(None, Policy::default());
+        shared.replace(opt_in.clone());
+        assert_eq!(*shared.current(), opt_in);
+        assert_eq!(shared.read().unwrap(), Policy::default());
+    }
+}
@@ -0,0 +1,179 @@
+use log::debug;
+use serde::Serialize;
+use std::{
+    collections::BTreeMap,
+    convert::Infallible,
+    sync::{Arc, RwLock},
+    time::{SystemTime, UNIX_EPOCH},
+};
+use warp::Filter;
+
+pub const POLICY: &str = "policy";
+pub const TLS: &str = "tls";
+pub const SELF_TEST: &str = "self_test";
+pub const SHUTDOWN: &str = "shutdown";
+/// Reports the kill switch, but always passes.
+pub const KILL_SWITCH: &str = "kill_switch";
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct CheckStatus {
+    pub ok: bool,
+    pub message: String,
+}
+
+#[derive(Serialize, Debug)]
+struct ReadinessReport {
+    ready: bool,
+    checks: BTreeMap<&'static str, CheckStatus>,
+}
+
+/// Readiness checks, reported by `/readyz`. The webhook is ready once every
+/// check has passed.
+#[derive(Debug)]
+pub struct Readiness {
+    checks: RwLock<BTreeMap<&'static str, CheckStatus>>,
+    certificate_expiry: RwLock<Option<i64>>,
+}
+
+impl Readiness {
+    pub fn new() -> Readiness {
+        let pending = CheckStatus {
+            ok: false,
+            message: "pending".to_string(),
+        };
+        let checks = [POLICY, TLS, SELF_TEST]
+            .into_iter()
+            .map(|check| (check, pending.clone()))
+            .collect();
+
+        Readiness {
+            checks: RwLock::new(checks),
+            certificate_expiry: RwLock::new(None),
+        }
+    }
+
+    pub fn set(&self, check: &'static str, result: Result<String, String>) {
+        let status = match result {
+            Ok(message) => CheckStatus { ok: true, message },
+            Err(message) => CheckStatus { ok: false, message },
+        };
+        self.checks.write().unwrap().insert(check, status);
+    }
+
+    /// Records a parsed key pair whose certificate expires at `expiry`. The
+    /// TLS check fails again once that time has passed.
+    pub fn set_certificate_expiry(&self, expiry: i64) {
+        *self.certificate_expiry.write().unwrap() = Some(expiry);
+        self.set(TLS, Ok(format!("certificate expires at {}", expiry)));
+    }
+
+    fn report(&self) -> ReadinessReport {
+        let mut checks = self.checks.read().unwrap().clone();
+
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|now| now.as_secs() as i64)
+            .unwrap_or_default();
+        if let Some(expiry) = *self.certificate_expiry.read().unwrap() {
+            if now >= expiry {
+                checks.insert(
+                    TLS,
+                    CheckStatus {
+                        ok: false,
+                        message: format!("certificate expired at {}", expiry),
+                    },
+                );
+            }
+        }
+
+        ReadinessReport {
+            ready: checks.values().all(|check| check.ok),
+            checks,
+        }
+    }
+}
+
+pub fn handler(
+    readiness: Arc<Readiness>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::get()
+        .and(warp::path("readyz"))
+        .and(with_readiness(readiness))
+        .map(|readiness: Arc<Readiness>| {
+            debug!("GET /readyz");
+            let report = readiness.report();
+            let status = if report.ready {
+                warp::http::StatusCode::OK
+            } else {
+                warp::http::StatusCode::SERVICE_UNAVAILABLE
+            };
+            warp::reply::with_status(warp::reply::json(&report), status)
+        })
+}
+
+fn with_readiness(
+    readiness: Arc<Readiness>,
+) -> impl Filter<Extract = (Arc<Readiness>,), Error = Infallible> + Clone {
+    warp::any().map(move || readiness.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+    use warp::test::request;
+
+    #[tokio::test]
+    async fn test_readyz_pending() {
+        let readiness = Arc::new(Readiness::new());
+        readiness.set(POLICY, Ok("loaded".to_string()));
+
+        let response = request()
+            .method("GET")
+            .path("/readyz")
+            .reply(&handler(readiness))
+            .await;
+
+        assert_eq!(response.status(), 503);
+        let body: Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(body["ready"], json!(false));
+        assert_eq!(body["checks"]["policy"]["ok"], json!(true));
+        assert_eq!(body["checks"]["self_test"]["message"], json!("pending"));
+    }
+
+    #[tokio::test]
+    async fn test_readyz_ready() {
+        let readiness = Arc::new(Readiness::new());
+        readiness.set(POLICY, Ok("loaded".to_string()));
+        readiness.set(SELF_TEST, Ok("passed".to_string()));
+        readiness.set_certificate_expiry(i64::MAX);
+
+        let response = request()
+            .method("GET")
+            .path("/readyz")
+            .reply(&handler(readiness))
+            .await;
+
+        assert_eq!(response.status(), 200);
+        let body: Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(body["ready"], json!(true));
+    }
+
+    #[tokio::test]
+    async fn test_readyz_certificate_expired() {
+        let readiness = Arc::new(Readiness::new());
+        readiness.set(POLICY, Ok("loaded".to_string()));
+        readiness.set(SELF_TEST, Ok("passed".to_string()));
+        readiness.set_certificate_expiry(0);
+
+        let response = request()
+            .method("GET")
+            .path("/readyz")
+            .reply(&handler(readiness))
+            .await;
+
+        assert_eq!(response.status(), 503);
+        let body: Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(body["checks"]["tls"]["ok"], json!(false));
+    }
+}
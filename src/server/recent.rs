@@ -0,0 +1,110 @@
+use serde::Serialize;
+use std::{collections::VecDeque, sync::Mutex};
+
+use super::audit::AuditEntry;
+
+/// One decision as kept in memory, with the message that was logged for it.
+#[derive(Serialize, Debug, Clone)]
+pub struct RecentDecision {
+    #[serde(flatten)]
+    pub entry: AuditEntry,
+    pub message: String,
+}
+
+/// The last `capacity` admission decisions, oldest dropped first.
+pub struct RecentDecisions {
+    capacity: usize,
+    decisions: Mutex<VecDeque<RecentDecision>>,
+}
+
+impl RecentDecisions {
+    pub fn new(capacity: usize) -> RecentDecisions {
+        RecentDecisions {
+            capacity,
+            decisions: Mutex::new(VecDeque::with_capacity(capacity)),
+        }
+    }
+
+    pub fn record(&self, entry: AuditEntry, message: String) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        let mut decisions = self.decisions.lock().unwrap_or_else(|e| e.into_inner());
+        if decisions.len() == self.capacity {
+            decisions.pop_front();
+        }
+        decisions.push_back(RecentDecision { entry, message });
+    }
+
+    /// Decisions for objects in `namespace` whose name starts with `name`,
+    /// newest first. `None` matches anything.
+    ///
+    /// Pods created by controllers are admitted before they're named, under
+    /// their `generateName`, so a pod's full name also finds them.
+    pub fn find(&self, namespace: Option<&str>, name: Option<&str>) -> Vec<RecentDecision> {
+        let decisions = self.decisions.lock().unwrap_or_else(|e| e.into_inner());
+        decisions
+            .iter()
+            .rev()
+            .filter(|decision| {
+                namespace.is_none_or(|namespace| decision.entry.namespace == namespace)
+            })
+            .filter(|decision| name.is_none_or(|name| name_matches(&decision.entry.name, name)))
+            .cloned()
+            .collect()
+    }
+}
+
+fn name_matches(recorded: &str, name: &str) -> bool {
+    recorded.starts_with(name) || (recorded.ends_with('-') && name.starts_with(recorded))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(uid: &str, namespace: &str, name: &str) -> AuditEntry {
+        AuditEntry {
+            timestamp: "2026-01-01T00:00:00.000Z".to_string(),
+            uid: uid.to_string(),
+            user: "test".to_string(),
+            kind: "Pod".to_string(),
+            namespace: namespace.to_string(),
+            name: name.to_string(),
+            operation: "CREATE".to_string(),
+            decision: "skipped".to_string(),
+            rule: "not_opted_in".to_string(),
+            patch: None,
+        }
+    }
+
+    #[test]
+    fn test_recent_decisions() {
+        let recent = RecentDecisions::new(3);
+        recent.record(entry("0", "default", "web"), "dropped".to_string());
+        recent.record(entry("1", "default", "web"), "skipping".to_string());
+        recent.record(entry("2", "default", "db"), "skipping".to_string());
+        recent.record(entry("3", "team-a", "web"), "skipping".to_string());
+        recent.record(entry("4", "team-a", "web-7d9f8-"), "mutating".to_string());
+
+        let uids = |decisions: Vec<RecentDecision>| -> Vec<String> {
+            decisions
+                .into_iter()
+                .map(|decision| decision.entry.uid)
+                .collect()
+        };
+        assert_eq!(uids(recent.find(None, None)), vec!["4", "3", "2"]);
+        assert_eq!(uids(recent.find(Some("default"), None)), vec!["2"]);
+        assert_eq!(uids(recent.find(None, Some("web"))), vec!["4", "3"]);
+        assert_eq!(uids(recent.find(None, Some("web-7d9f8-x2k4p"))), vec!["4"]);
+        assert_eq!(
+            uids(recent.find(Some("team-a"), Some("db"))),
+            Vec::<String>::new()
+        );
+
+        let disabled = RecentDecisions::new(0);
+        disabled.record(entry("0", "default", "web"), "skipping".to_string());
+        assert!(disabled.find(None, None).is_empty());
+    }
+}
@@ -0,0 +1,279 @@
+use anyhow::{anyhow, Result};
+use serde_json::Value;
+
+const REDACTED: &str = "[redacted]";
+const LAST_APPLIED_ANNOTATION: &str = "kubectl.kubernetes.io/last-applied-configuration";
+/// Annotations with any of these in their name are assumed to hold secrets.
+const SECRET_ANNOTATION_WORDS: &[&str] = &["token", "secret", "password", "key", "credential"];
+
+/// Masks sensitive content in request bodies before they are logged.
+///
+/// Container `env` values, `managedFields`, `last-applied-configuration` and
+/// secret-looking annotations are always masked. Extra fields can be masked by JSON Pointer,
+/// where a `*` segment matches every key or index.
+pub struct Redactor {
+    paths: Vec<Vec<String>>,
+    max_bytes: usize,
+}
+
+impl Default for Redactor {
+    fn default() -> Self {
+        Redactor {
+            paths: Vec::new(),
+            max_bytes: 4096,
+        }
+    }
+}
+
+impl Redactor {
+    pub fn new(paths: &[&str], max_bytes: usize) -> Result<Redactor> {
+        let paths = paths
+            .iter()
+            .map(|path| parse_pointer(path))
+            .collect::<Result<_>>()?;
+
+        Ok(Redactor { paths, max_bytes })
+    }
+
+    /// Returns a loggable rendering of `body`, truncated to the configured size.
+    pub fn redact(&self, body: &[u8]) -> String {
+        let Ok(mut value) = serde_json::from_slice::<Value>(body) else {
+            return format!("<{} bytes, not valid JSON>", body.len());
+        };
+
+        mask_builtin(&mut value);
+        for path in &self.paths {
+            mask_path(&mut value, path);
+        }
+
+        truncate(value.to_string(), self.max_bytes)
+    }
+}
+
+fn parse_pointer(path: &str) -> Result<Vec<String>> {
+    let Some(segments) = path.strip_prefix('/') else {
+        return Err(anyhow!("{} is not a JSON Pointer", path));
+    };
+
+    Ok(segments
+        .split('/')
+        .map(|segment| segment.replace("~1", "/").replace("~0", "~"))
+        .collect())
+}
+
+fn mask_builtin(value: &mut Value) {
+    match value {
+        Value::Object(map) => {
+            for (key, value) in map.iter_mut() {
+                match key.as_str() {
+                    "managedFields" | LAST_APPLIED_ANNOTATION => *value = REDACTED.into(),
+                    "env" => mask_env(value),
+                    "annotations" => mask_annotations(value),
+                    _ => mask_builtin(value),
+                }
+            }
+        }
+        Value::Array(items) => items.iter_mut().for_each(mask_builtin),
+        _ => {}
+    }
+}
+
+/// Masks annotations that may carry credentials, going by their name.
+fn mask_annotations(annotations: &mut Value) {
+    let Value::Object(map) = annotations else {
+        return mask_builtin(annotations);
+    };
+
+    for (name, value) in map.iter_mut() {
+        let name = name.to_lowercase();
+        if name == LAST_APPLIED_ANNOTATION
+            || SECRET_ANNOTATION_WORDS
+                .iter()
+                .any(|word| name.contains(word))
+        {
+            *value = REDACTED.into();
+        }
+    }
+}
+
+/// Masks the values of a container's environment variables, keeping their
+/// names and `valueFrom` references.
+fn mask_env(env: &mut Value) {
+    let Value::Array(vars) = env else {
+        return;
+    };
+
+    for var in vars {
+        if let Some(value) = var.get_mut("value") {
+            *value = REDACTED.into();
+        }
+    }
+}
+
+fn mask_path(value: &mut Value, path: &[String]) {
+    let Some((segment, rest)) = path.split_first() else {
+        *value = REDACTED.into();
+        return;
+    };
+
+    match value {
+        Value::Object(map) if segment == "*" => {
+            map.values_mut().for_each(|value| mask_path(value, rest))
+        }
+        Value::Array(items) if segment == "*" => {
+            items.iter_mut().for_each(|value| mask_path(value, rest))
+        }
+        Value::Object(map) => {
+            if let Some(value) = map.get_mut(segment) {
+                mask_path(value, rest);
+            }
+        }
+        Value::Array(items) => {
+            if let Some(value) = segment
+                .parse::<usize>()
+                .ok()
+                .and_then(|index| items.get_mut(index))
+            {
+                mask_path(value, rest);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn truncate(mut body: String, max_bytes: usize) -> String {
+    if body.len() <= max_bytes {
+        return body;
+    }
+
+    let mut end = max_bytes;
+    while !body.is_char_boundary(end) {
+        end -= 1;
+    }
+    let truncated = body.len() - end;
+    body.truncate(end);
+    format!("{}... ({} bytes truncated)", body, truncated)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn redact(redactor: &Redactor, body: Value) -> Value {
+        serde_json::from_str(&redactor.redact(body.to_string().as_bytes())).unwrap()
+    }
+
+    #[test]
+    fn test_builtin_redaction() {
+        let body = json!({
+            "request": {
+                "object": {
+                    "metadata": {
+                        "name": "test-pod",
+                        "annotations": {
+                            LAST_APPLIED_ANNOTATION: "{\"apiVersion\":\"v1\"}",
+                            "team": "platform"
+                        },
+                        "managedFields": [{"manager": "kubectl"}]
+                    },
+                    "spec": {
+                        "containers": [{
+                            "name": "app",
+                            "env": [
+                                {"name": "PASSWORD", "value": "hunter2"},
+                                {"name": "TOKEN", "valueFrom": {"secretKeyRef": {"name": "token", "key": "token"}}}
+                            ]
+                        }]
+                    }
+                }
+            }
+        });
+
+        let redacted = redact(&Redactor::default(), body);
+        let object = &redacted["request"]["object"];
+        assert_eq!(object["metadata"]["name"], "test-pod");
+        assert_eq!(object["metadata"]["annotations"]["team"], "platform");
+        assert_eq!(
+            object["metadata"]["annotations"][LAST_APPLIED_ANNOTATION],
+            REDACTED
+        );
+        assert_eq!(object["metadata"]["managedFields"], REDACTED);
+
+        let env = &object["spec"]["containers"][0]["env"];
+        assert_eq!(env[0], json!({"name": "PASSWORD", "value": REDACTED}));
+        assert_eq!(env[1]["valueFrom"]["secretKeyRef"]["name"], "token");
+    }
+
+    #[test]
+    fn test_secret_annotations() {
+        let body = json!({
+            "metadata": {
+                "annotations": {
+                    "vault.example.com/token": "s.abc123",
+                    "example.com/DB-Password": "hunter2",
+                    "example.com/api-key": "abc123",
+                    "example.com/aws-credentials": "[default]",
+                    "example.com/client-secret": "s3cret",
+                    "team": "platform",
+                    "example.com/owner": "payments"
+                }
+            }
+        });
+
+        let redacted = redact(&Redactor::default(), body);
+        let annotations = redacted["metadata"]["annotations"].as_object().unwrap();
+        for (name, value) in annotations {
+            if name == "team" || name == "example.com/owner" {
+                assert_ne!(value, REDACTED, "{}", name);
+            } else {
+                assert_eq!(value, REDACTED, "{}", name);
+            }
+        }
+    }
+
+    #[test]
+    fn test_configured_paths() {
+        let redactor = Redactor::new(
+            &[
+                "/request/object/metadata/annotations/example.com~1token",
+                "/request/object/spec/containers/*/args",
+            ],
+            4096,
+        )
+        .unwrap();
+        let body = json!({
+            "request": {
+                "object": {
+                    "metadata": {"annotations": {"example.com/token": "s3cret", "team": "platform"}},
+                    "spec": {"containers": [{"args": ["--password=s3cret"]}, {"name": "sidecar"}]}
+                }
+            }
+        });
+
+        let redacted = redact(&redactor, body);
+        let object = &redacted["request"]["object"];
+        assert_eq!(
+            object["metadata"]["annotations"]["example.com/token"],
+            REDACTED
+        );
+        assert_eq!(object["metadata"]["annotations"]["team"], "platform");
+        assert_eq!(object["spec"]["containers"][0]["args"], REDACTED);
+        assert_eq!(object["spec"]["containers"][1], json!({"name": "sidecar"}));
+
+        assert!(Redactor::new(&["request.object"], 4096).is_err());
+    }
+
+    #[test]
+    fn test_truncation() {
+        let redactor = Redactor::new(&[], 16).unwrap();
+        let logged = redactor.redact(json!({"message": "x".repeat(100)}).to_string().as_bytes());
+        assert_eq!(logged, r#"{"message":"xxxx... (98 bytes truncated)"#);
+
+        assert_eq!(truncate("héllo".to_string(), 2), "h... (5 bytes truncated)");
+        assert_eq!(
+            redactor.redact(b"password=hunter2"),
+            "<16 bytes, not valid JSON>"
+        );
+    }
+}
@@ -0,0 +1,64 @@
+use anyhow::{anyhow, Result};
+use std::future::Future;
+use tokio::{
+    signal::unix::{signal, SignalKind},
+    sync::watch,
+};
+
+/// Fans a single shutdown out to every listener.
+pub struct Shutdown {
+    tx: watch::Sender<bool>,
+}
+
+impl Shutdown {
+    pub fn new() -> Shutdown {
+        let (tx, _) = watch::channel(false);
+        Shutdown { tx }
+    }
+
+    /// Resolves once `trigger` has been called.
+    pub fn signal(&self) -> impl Future<Output = ()> + Send + 'static {
+        let mut rx = self.tx.subscribe();
+        async move {
+            let _ = rx.wait_for(|stop| *stop).await;
+        }
+    }
+
+    pub fn trigger(&self) {
+        self.tx.send_replace(true);
+    }
+}
+
+/// Waits for SIGTERM or SIGINT and returns the name of the signal received.
+pub async fn wait_for_signal() -> Result<&'static str> {
+    let mut sigterm = signal(SignalKind::terminate())
+        .map_err(|e| anyhow!("Error installing SIGTERM handler: {}", e))?;
+    let mut sigint = signal(SignalKind::interrupt())
+        .map_err(|e| anyhow!("Error installing SIGINT handler: {}", e))?;
+
+    tokio::select! {
+        _ = sigterm.recv() => Ok("SIGTERM"),
+        _ = sigint.recv() => Ok("SIGINT"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_shutdown_signal() {
+        let shutdown = Shutdown::new();
+        let signal = tokio::spawn(shutdown.signal());
+
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert!(!signal.is_finished());
+
+        shutdown.trigger();
+        tokio::time::timeout(Duration::from_secs(1), signal)
+            .await
+            .expect("shutdown signal did not resolve")
+            .unwrap();
+    }
+}
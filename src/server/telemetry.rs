@@ -0,0 +1,177 @@
+use anyhow::{anyhow, Result};
+use opentelemetry::{
+    global::{self, BoxedSpan, BoxedTracer},
+    propagation::Extractor,
+    trace::{Link, Span, SpanBuilder, SpanContext, SpanKind, Status, TraceContextExt, Tracer},
+    Context, KeyValue,
+};
+use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+use opentelemetry_sdk::{propagation::TraceContextPropagator, trace::SdkTracerProvider, Resource};
+use std::{convert::Infallible, time::SystemTime};
+use warp::{http::HeaderMap, Filter};
+
+/// Sets up span export over OTLP/HTTP to `endpoint`, e.g.
+/// `http://localhost:4318/v1/traces`. Until this is called spans are no-ops.
+pub fn init(endpoint: &str) -> Result<SdkTracerProvider> {
+    let exporter = SpanExporter::builder()
+        .with_http()
+        .with_endpoint(endpoint)
+        .build()
+        .map_err(|e| anyhow!("Error building OTLP exporter for {}: {}", endpoint, e))?;
+
+    let provider = SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_resource(
+            Resource::builder()
+                .with_service_name(env!("CARGO_PKG_NAME"))
+                .build(),
+        )
+        .build();
+
+    global::set_tracer_provider(provider.clone());
+    global::set_text_map_propagator(TraceContextPropagator::new());
+    Ok(provider)
+}
+
+pub fn tracer() -> BoxedTracer {
+    global::tracer(env!("CARGO_PKG_NAME"))
+}
+
+/// Starts a child span of `cx`. It ends when dropped.
+pub fn child_span(cx: &Context, name: &'static str) -> BoxedSpan {
+    tracer().start_with_context(name, cx)
+}
+
+/// The `tls_accept` span of the connection a request arrived on.
+#[derive(Clone, Debug)]
+pub struct AcceptSpan(pub SpanContext);
+
+/// The span covering one admission request, continuing the caller's trace
+/// when the API server sends a `traceparent` header.
+#[derive(Clone)]
+pub struct RequestContext {
+    pub cx: Context,
+    received: SystemTime,
+}
+
+impl Default for RequestContext {
+    /// A request outside of any trace.
+    fn default() -> Self {
+        RequestContext {
+            cx: Context::new(),
+            received: SystemTime::now(),
+        }
+    }
+}
+
+impl RequestContext {
+    /// Records the time between the headers arriving and now as a span.
+    pub fn record_body_read(&self) {
+        let tracer = tracer();
+        tracer
+            .span_builder("read_body")
+            .with_start_time(self.received)
+            .start_with_context(&tracer, &self.cx)
+            .end();
+    }
+
+    pub fn set_attribute(&self, key: &'static str, value: impl Into<opentelemetry::Value>) {
+        self.cx.span().set_attribute(KeyValue::new(key, value));
+    }
+
+    pub fn set_error(&self, message: impl Into<String>) {
+        self.cx.span().set_status(Status::error(message.into()));
+    }
+}
+
+/// The admission span, linked to the handshake of its connection when it
+/// came in over TLS.
+fn admission_span(accept: Option<AcceptSpan>) -> SpanBuilder {
+    let builder = tracer()
+        .span_builder("admission")
+        .with_kind(SpanKind::Server);
+    match accept {
+        Some(AcceptSpan(span_context)) if span_context.is_valid() => {
+            builder.with_links(vec![Link::with_context(span_context)])
+        }
+        _ => builder,
+    }
+}
+
+pub fn request_context() -> impl Filter<Extract = (RequestContext,), Error = Infallible> + Clone {
+    warp::header::headers_cloned()
+        .and(warp::ext::optional::<AcceptSpan>())
+        .map(|headers: HeaderMap, accept: Option<AcceptSpan>| {
+            let parent = global::get_text_map_propagator(|propagator| {
+                propagator.extract(&Headers(&headers))
+            });
+            let span = admission_span(accept).start_with_context(&tracer(), &parent);
+
+            RequestContext {
+                cx: parent.with_span(span),
+                received: SystemTime::now(),
+            }
+        })
+}
+
+struct Headers<'a>(&'a HeaderMap);
+
+impl Extractor for Headers<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|value| value.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(|key| key.as_str()).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use opentelemetry::{
+        propagation::TextMapPropagator,
+        trace::{SpanId, TraceFlags, TraceId, TraceState},
+    };
+
+    #[test]
+    fn test_extracts_traceparent() {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "traceparent",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01"
+                .parse()
+                .unwrap(),
+        );
+
+        let cx = TraceContextPropagator::new().extract(&Headers(&headers));
+        let span = cx.span();
+        let span_context = span.span_context();
+        assert!(span_context.is_remote());
+        assert_eq!(
+            span_context.trace_id().to_string(),
+            "4bf92f3577b34da6a3ce929d0e0e4736"
+        );
+        assert_eq!(span_context.span_id().to_string(), "00f067aa0ba902b7");
+    }
+
+    #[test]
+    fn test_admission_span_links_tls_accept() {
+        assert!(admission_span(None).links.is_none());
+        let invalid = AcceptSpan(SpanContext::empty_context());
+        assert!(admission_span(Some(invalid)).links.is_none());
+
+        let accept = SpanContext::new(
+            TraceId::from_hex("4bf92f3577b34da6a3ce929d0e0e4736").unwrap(),
+            SpanId::from_hex("00f067aa0ba902b7").unwrap(),
+            TraceFlags::SAMPLED,
+            false,
+            TraceState::default(),
+        );
+        let links = admission_span(Some(AcceptSpan(accept.clone())))
+            .links
+            .unwrap();
+        assert_eq!(links.len(), 1);
+        assert_eq!(links[0].span_context, accept);
+    }
+}
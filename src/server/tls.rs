@@ -0,0 +1,950 @@
+use anyhow::{anyhow, Result};
+use log::{debug, warn};
+use opentelemetry::{
+    trace::{Span, SpanKind, Status, Tracer},
+    KeyValue,
+};
+use std::{
+    fs,
+    io::{self, BufReader},
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    pin::Pin,
+    sync::{Arc, RwLock},
+    task::{Context, Poll},
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    net::{TcpListener, TcpStream},
+    sync::mpsc,
+    task::JoinHandle,
+};
+use tokio_rustls::{
+    rustls::{
+        client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+        crypto::{
+            ring::default_provider, verify_tls12_signature, verify_tls13_signature, CryptoProvider,
+            WebPkiSupportedAlgorithms,
+        },
+        pki_types::{CertificateDer, PrivateKeyDer, ServerName, UnixTime},
+        server::danger::{ClientCertVerified, ClientCertVerifier},
+        server::{ClientHello, ResolvesServerCert, WebPkiClientVerifier},
+        sign::CertifiedKey,
+        version::{TLS12, TLS13},
+        CertificateError, ClientConfig, ClientConnection, Connection, DigitallySignedStruct,
+        DistinguishedName, Error as TlsError, ProtocolVersion, RootCertStore, ServerConfig,
+        ServerConnection, SignatureScheme, SupportedCipherSuite, SupportedProtocolVersion,
+    },
+    server::TlsStream,
+    TlsAcceptor,
+};
+use warp::hyper::server::accept::Accept;
+use x509_parser::{certificate::X509Certificate, extensions::GeneralName, prelude::FromDer};
+
+use super::accept::AcceptBackoff;
+use super::{metrics, telemetry, telemetry::AcceptSpan};
+
+/// Handshakes taking longer than this are abandoned.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A parsed serving certificate chain and its private key.
+pub struct KeyPair {
+    certs: Vec<CertificateDer<'static>>,
+    key: PrivateKeyDer<'static>,
+    /// Expiry of the leaf certificate as a unix timestamp.
+    pub expiry: i64,
+}
+
+/// The serving certificate, loaded from `crt_path` and `key_path`. Replacing
+/// it affects handshakes that start afterwards, so listeners pick up a renewed
+/// certificate without restarting.
+#[derive(Debug)]
+pub struct ServingCert {
+    crt_path: String,
+    key_path: String,
+    key: RwLock<Arc<CertifiedKey>>,
+}
+
+impl ServingCert {
+    pub fn new(crt_path: String, key_path: String, key_pair: KeyPair) -> Result<ServingCert> {
+        Ok(ServingCert {
+            crt_path,
+            key_path,
+            key: RwLock::new(certified_key(key_pair)?),
+        })
+    }
+
+    /// Reads the key pair from disk again, without replacing the current one.
+    pub fn load_key_pair(&self) -> Result<KeyPair> {
+        load_key_pair(&self.crt_path, &self.key_path)
+    }
+
+    pub fn replace(&self, key_pair: KeyPair) -> Result<()> {
+        *self.key.write().unwrap() = certified_key(key_pair)?;
+        Ok(())
+    }
+}
+
+impl ResolvesServerCert for ServingCert {
+    fn resolve(&self, _client_hello: ClientHello) -> Option<Arc<CertifiedKey>> {
+        Some(self.key.read().unwrap().clone())
+    }
+}
+
+fn certified_key(key_pair: KeyPair) -> Result<Arc<CertifiedKey>> {
+    let key = default_provider()
+        .key_provider
+        .load_private_key(key_pair.key)
+        .map_err(|e| anyhow!("Error loading TLS private key: {}", e))?;
+    Ok(Arc::new(CertifiedKey::new(key_pair.certs, key)))
+}
+
+/// Client certificate verification settings.
+pub struct ClientAuth {
+    /// PEM bundle of CAs trusted to sign client certificates.
+    pub ca_path: String,
+    /// Subject CNs or DNS SANs allowed to connect. Any client certificate
+    /// signed by the CA is accepted when empty.
+    pub allowed_names: Vec<String>,
+}
+
+/// Protocol settings for the serving listener.
+pub struct TlsSettings {
+    pub min_version: &'static SupportedProtocolVersion,
+    pub cipher_suites: Vec<SupportedCipherSuite>,
+    pub alpn_protocols: Vec<Vec<u8>>,
+}
+
+impl Default for TlsSettings {
+    fn default() -> Self {
+        TlsSettings {
+            min_version: &TLS12,
+            cipher_suites: default_provider().cipher_suites,
+            alpn_protocols: vec![b"h2".to_vec(), b"http/1.1".to_vec()],
+        }
+    }
+}
+
+pub fn parse_min_version(version: &str) -> Result<&'static SupportedProtocolVersion> {
+    match version {
+        "1.2" => Ok(&TLS12),
+        "1.3" => Ok(&TLS13),
+        _ => Err(anyhow!(
+            "Unsupported TLS version {}, expected 1.2 or 1.3",
+            version
+        )),
+    }
+}
+
+/// Looks up cipher suites by their IANA names, e.g. `TLS13_AES_256_GCM_SHA384`.
+pub fn parse_cipher_suites(names: &[&str]) -> Result<Vec<SupportedCipherSuite>> {
+    let supported = default_provider().cipher_suites;
+
+    names
+        .iter()
+        .map(|name| {
+            supported
+                .iter()
+                .find(|suite| format!("{:?}", suite.suite()) == *name)
+                .copied()
+                .ok_or_else(|| {
+                    let supported: Vec<String> = supported
+                        .iter()
+                        .map(|suite| format!("{:?}", suite.suite()))
+                        .collect();
+                    anyhow!(
+                        "Unsupported cipher suite {}, expected one of {}",
+                        name,
+                        supported.join(", ")
+                    )
+                })
+        })
+        .collect()
+}
+
+pub fn parse_alpn_protocols(names: &[&str]) -> Result<Vec<Vec<u8>>> {
+    names
+        .iter()
+        .map(|name| match *name {
+            "h2" | "http/1.1" => Ok(name.as_bytes().to_vec()),
+            _ => Err(anyhow!(
+                "Unsupported ALPN protocol {}, expected h2 or http/1.1",
+                name
+            )),
+        })
+        .collect()
+}
+
+/// Loads a certificate chain and private key. Both may come from the same
+/// combined PEM bundle. The chain must start with the serving certificate,
+/// followed by any intermediates.
+pub fn load_key_pair(crt_path: &str, key_path: &str) -> Result<KeyPair> {
+    let certs = load_certs(crt_path)?;
+    let leaf = parse_certificate(&certs[0])
+        .map_err(|e| anyhow!("Error parsing certificate in {}: {}", crt_path, e))?;
+    if leaf.is_ca() {
+        return Err(anyhow!(
+            "First certificate in {} is a CA certificate, the serving certificate must come before its chain",
+            crt_path
+        ));
+    }
+    let expiry = leaf.validity().not_after.timestamp();
+
+    let key = match fs::File::open(key_path) {
+        Err(e) => return Err(anyhow!("Error reading {}: {}", key_path, e)),
+        Ok(key) => key,
+    };
+    let key = match rustls_pemfile::private_key(&mut BufReader::new(key)) {
+        Err(e) => return Err(anyhow!("Error parsing private key in {}: {}", key_path, e)),
+        Ok(None) => return Err(anyhow!("No private key found in {}", key_path)),
+        Ok(Some(key)) => key,
+    };
+
+    if let Err(e) = check_key_matches(&certs, &key) {
+        return Err(anyhow!(
+            "Private key in {} does not work with certificate in {}: {}",
+            key_path,
+            crt_path,
+            e
+        ));
+    }
+
+    Ok(KeyPair { certs, key, expiry })
+}
+
+/// Runs a handshake against the key pair in memory, so a key that doesn't
+/// belong to its certificate fails at startup instead of on every connection.
+fn check_key_matches(
+    certs: &[CertificateDer<'static>],
+    key: &PrivateKeyDer<'static>,
+) -> Result<()> {
+    let server = ServerConfig::builder()
+        .with_no_client_auth()
+        .with_single_cert(certs.to_vec(), key.clone_key())?;
+    let client = ClientConfig::builder()
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(SignatureOnlyVerifier(
+            default_provider().signature_verification_algorithms,
+        )))
+        .with_no_client_auth();
+
+    let server_name = ServerName::IpAddress(IpAddr::V4(Ipv4Addr::LOCALHOST).into());
+    let mut client = Connection::from(ClientConnection::new(Arc::new(client), server_name)?);
+    let mut server = Connection::from(ServerConnection::new(Arc::new(server))?);
+
+    while client.is_handshaking() || server.is_handshaking() {
+        if !client.wants_write() && !server.wants_write() {
+            return Err(anyhow!("handshake stalled"));
+        }
+        transfer(&mut client, &mut server)?;
+        transfer(&mut server, &mut client)?;
+    }
+
+    Ok(())
+}
+
+fn transfer(from: &mut Connection, to: &mut Connection) -> Result<()> {
+    let mut buf = Vec::new();
+    while from.wants_write() {
+        from.write_tls(&mut buf)?;
+    }
+
+    let mut rd = buf.as_slice();
+    while !rd.is_empty() {
+        to.read_tls(&mut rd)?;
+        to.process_new_packets()?;
+    }
+
+    Ok(())
+}
+
+/// Accepts any server certificate but still checks handshake signatures,
+/// which is all [`check_key_matches`] needs.
+#[derive(Debug)]
+struct SignatureOnlyVerifier(WebPkiSupportedAlgorithms);
+
+impl ServerCertVerifier for SignatureOnlyVerifier {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, TlsError> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, TlsError> {
+        verify_tls12_signature(message, cert, dss, &self.0)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, TlsError> {
+        verify_tls13_signature(message, cert, dss, &self.0)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.supported_schemes()
+    }
+}
+
+fn load_certs(path: &str) -> Result<Vec<CertificateDer<'static>>> {
+    let pem = match fs::File::open(path) {
+        Err(e) => return Err(anyhow!("Error reading {}: {}", path, e)),
+        Ok(pem) => pem,
+    };
+
+    let certs = rustls_pemfile::certs(&mut BufReader::new(pem))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| anyhow!("Error parsing certificates in {}: {}", path, e))?;
+    if certs.is_empty() {
+        return Err(anyhow!("No certificates found in {}", path));
+    }
+
+    Ok(certs)
+}
+
+fn parse_certificate<'a>(cert: &'a CertificateDer<'_>) -> Result<X509Certificate<'a>> {
+    X509Certificate::from_der(cert)
+        .map(|(_, cert)| cert)
+        .map_err(|e| anyhow!("{}", e))
+}
+
+pub fn server_config(
+    cert: Arc<ServingCert>,
+    settings: &TlsSettings,
+    client_auth: Option<&ClientAuth>,
+) -> Result<Arc<ServerConfig>> {
+    let provider = Arc::new(CryptoProvider {
+        cipher_suites: settings.cipher_suites.clone(),
+        ..default_provider()
+    });
+    let versions: &[&SupportedProtocolVersion] =
+        if settings.min_version.version == ProtocolVersion::TLSv1_3 {
+            &[&TLS13]
+        } else {
+            &[&TLS12, &TLS13]
+        };
+    let builder = ServerConfig::builder_with_provider(provider.clone())
+        .with_protocol_versions(versions)
+        .map_err(|e| anyhow!("Error configuring TLS protocol versions: {}", e))?;
+
+    let builder = match client_auth {
+        None => builder.with_no_client_auth(),
+        Some(client_auth) => {
+            let mut roots = RootCertStore::empty();
+            let (added, _) = roots.add_parsable_certificates(load_certs(&client_auth.ca_path)?);
+            if added == 0 {
+                return Err(anyhow!(
+                    "No usable CA certificates in {}",
+                    client_auth.ca_path
+                ));
+            }
+
+            let inner = WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider)
+                .build()
+                .map_err(|e| anyhow!("Error building client verifier: {}", e))?;
+            builder.with_client_cert_verifier(Arc::new(AllowlistClientVerifier {
+                inner,
+                allowed_names: client_auth.allowed_names.clone(),
+            }))
+        }
+    };
+
+    let mut config = builder.with_cert_resolver(cert);
+    config.alpn_protocols = settings.alpn_protocols.clone();
+
+    Ok(Arc::new(config))
+}
+
+/// Verifies client certificates against the configured CAs, then checks the
+/// subject CN and DNS SANs against an allowlist.
+#[derive(Debug)]
+struct AllowlistClientVerifier {
+    inner: Arc<dyn ClientCertVerifier>,
+    allowed_names: Vec<String>,
+}
+
+impl ClientCertVerifier for AllowlistClientVerifier {
+    fn root_hint_subjects(&self) -> &[DistinguishedName] {
+        self.inner.root_hint_subjects()
+    }
+
+    fn verify_client_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        now: UnixTime,
+    ) -> Result<ClientCertVerified, TlsError> {
+        let verified = self
+            .inner
+            .verify_client_cert(end_entity, intermediates, now)?;
+        if self.allowed_names.is_empty() {
+            return Ok(verified);
+        }
+
+        let names = parse_certificate(end_entity)
+            .map(|cert| client_names(&cert))
+            .map_err(|_| TlsError::InvalidCertificate(CertificateError::BadEncoding))?;
+        if names.iter().any(|name| self.allowed_names.contains(name)) {
+            return Ok(verified);
+        }
+
+        warn!("rejecting client certificate for {:?}", names);
+        Err(TlsError::InvalidCertificate(
+            CertificateError::ApplicationVerificationFailure,
+        ))
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, TlsError> {
+        self.inner.verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, TlsError> {
+        self.inner.verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.inner.supported_verify_schemes()
+    }
+}
+
+/// Subject common names and DNS subject alternative names of a certificate.
+fn client_names(cert: &X509Certificate<'_>) -> Vec<String> {
+    let mut names: Vec<String> = cert
+        .subject()
+        .iter_common_name()
+        .filter_map(|cn| cn.as_str().ok())
+        .map(str::to_string)
+        .collect();
+
+    if let Ok(Some(san)) = cert.subject_alternative_name() {
+        names.extend(
+            san.value
+                .general_names
+                .iter()
+                .filter_map(|name| match name {
+                    GeneralName::DNSName(dns) => Some(dns.to_string()),
+                    _ => None,
+                }),
+        );
+    }
+
+    names
+}
+
+/// Metrics label for a failed handshake.
+fn handshake_failure_reason(err: &io::Error) -> &'static str {
+    match err.get_ref().and_then(|e| e.downcast_ref::<TlsError>()) {
+        Some(TlsError::NoCertificatesPresented) => "client_certificate_missing",
+        Some(TlsError::InvalidCertificate(CertificateError::ApplicationVerificationFailure)) => {
+            "client_not_allowed"
+        }
+        Some(TlsError::InvalidCertificate(_)) => "client_certificate_invalid",
+        _ => "handshake_error",
+    }
+}
+
+/// An accepted TLS connection, remembering its handshake span so requests on
+/// it can link back to it.
+pub struct TlsConnection {
+    stream: TlsStream<TcpStream>,
+    accept_span: AcceptSpan,
+}
+
+impl TlsConnection {
+    pub fn accept_span(&self) -> AcceptSpan {
+        self.accept_span.clone()
+    }
+}
+
+impl AsyncRead for TlsConnection {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for TlsConnection {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.stream).poll_write(cx, buf)
+    }
+
+    fn poll_write_vectored(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[io::IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.stream).poll_write_vectored(cx, bufs)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.stream.is_write_vectored()
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_shutdown(cx)
+    }
+}
+
+/// TLS connections for hyper. Handshakes run on their own tasks so a slow
+/// client can't hold up the accept loop.
+pub struct TlsIncoming {
+    local_addr: SocketAddr,
+    rx: mpsc::Receiver<TlsConnection>,
+    accept_task: JoinHandle<()>,
+}
+
+impl TlsIncoming {
+    pub async fn bind(addr: SocketAddr, config: Arc<ServerConfig>) -> Result<TlsIncoming> {
+        let listener = TcpListener::bind(addr)
+            .await
+            .map_err(|e| anyhow!("Error binding listener to {}: {}", addr, e))?;
+        let local_addr = listener.local_addr()?;
+        let acceptor = TlsAcceptor::from(config);
+        let (tx, rx) = mpsc::channel(64);
+
+        let accept_task = tokio::spawn(async move {
+            let mut backoff = AcceptBackoff::new();
+            loop {
+                let (stream, peer) = match listener.accept().await {
+                    Ok(conn) => conn,
+                    Err(e) => {
+                        tokio::time::sleep(backoff.failed(&e)).await;
+                        continue;
+                    }
+                };
+                backoff.reset();
+
+                let acceptor = acceptor.clone();
+                let tx = tx.clone();
+                tokio::spawn(async move {
+                    let tracer = telemetry::tracer();
+                    let mut span = tracer
+                        .span_builder("tls_accept")
+                        .with_kind(SpanKind::Server)
+                        .with_attributes([KeyValue::new("client.address", peer.to_string())])
+                        .start(&tracer);
+
+                    match tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+                        Ok(Ok(stream)) => {
+                            span.end();
+                            let accept_span = AcceptSpan(span.span_context().clone());
+                            let _ = tx
+                                .send(TlsConnection {
+                                    stream,
+                                    accept_span,
+                                })
+                                .await;
+                        }
+                        Ok(Err(e)) => {
+                            debug!("TLS handshake with {} failed: {}", peer, e);
+                            let reason = handshake_failure_reason(&e);
+                            metrics::observe_tls_handshake_failure(reason);
+                            span.set_status(Status::error(reason));
+                        }
+                        Err(_) => {
+                            debug!("TLS handshake with {} timed out", peer);
+                            metrics::observe_tls_handshake_failure("timeout");
+                            span.set_status(Status::error("timeout"));
+                        }
+                    }
+                });
+            }
+        });
+
+        Ok(TlsIncoming {
+            local_addr,
+            rx,
+            accept_task,
+        })
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+}
+
+impl Accept for TlsIncoming {
+    type Conn = TlsConnection;
+    type Error = io::Error;
+
+    fn poll_accept(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Self::Conn, Self::Error>>> {
+        self.rx.poll_recv(cx).map(|conn| conn.map(Ok))
+    }
+}
+
+impl Drop for TlsIncoming {
+    fn drop(&mut self) {
+        self.accept_task.abort();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::future::poll_fn;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio_rustls::{
+        client::TlsStream as ClientTlsStream, rustls::AlertDescription, TlsConnector,
+    };
+
+    const CERTS_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/data/certs");
+
+    fn cert_path(name: &str) -> String {
+        format!("{}/{}", CERTS_DIR, name)
+    }
+
+    async fn serve(client_auth: Option<ClientAuth>) -> TlsIncoming {
+        serve_with("tls.crt", "tls.key", &TlsSettings::default(), client_auth).await
+    }
+
+    fn serving_cert(crt: &str, key: &str) -> ServingCert {
+        let (crt_path, key_path) = (cert_path(crt), cert_path(key));
+        let key_pair = load_key_pair(&crt_path, &key_path).unwrap();
+        ServingCert::new(crt_path, key_path, key_pair).unwrap()
+    }
+
+    async fn serve_with(
+        crt: &str,
+        key: &str,
+        settings: &TlsSettings,
+        client_auth: Option<ClientAuth>,
+    ) -> TlsIncoming {
+        let cert = serving_cert(crt, key);
+        let config = server_config(Arc::new(cert), settings, client_auth.as_ref()).unwrap();
+        TlsIncoming::bind("127.0.0.1:0".parse().unwrap(), config)
+            .await
+            .unwrap()
+    }
+
+    fn api_server_only() -> Option<ClientAuth> {
+        Some(ClientAuth {
+            ca_path: cert_path("ca.crt"),
+            allowed_names: vec!["kube-apiserver".to_string()],
+        })
+    }
+
+    async fn connect(
+        addr: SocketAddr,
+        client: Option<&str>,
+    ) -> io::Result<ClientTlsStream<TcpStream>> {
+        connect_with(addr, "ca.crt", &[&TLS12, &TLS13], client).await
+    }
+
+    async fn connect_with(
+        addr: SocketAddr,
+        ca: &str,
+        versions: &[&'static SupportedProtocolVersion],
+        client: Option<&str>,
+    ) -> io::Result<ClientTlsStream<TcpStream>> {
+        let mut roots = RootCertStore::empty();
+        roots.add_parsable_certificates(load_certs(&cert_path(ca)).unwrap());
+        let builder =
+            ClientConfig::builder_with_protocol_versions(versions).with_root_certificates(roots);
+        let config = match client {
+            None => builder.with_no_client_auth(),
+            Some(name) => {
+                let key_pair = load_key_pair(
+                    &cert_path(&format!("{}.crt", name)),
+                    &cert_path(&format!("{}.key", name)),
+                )
+                .unwrap();
+                builder
+                    .with_client_auth_cert(key_pair.certs, key_pair.key)
+                    .unwrap()
+            }
+        };
+
+        let stream = TcpStream::connect(addr).await?;
+        let mut stream = TlsConnector::from(Arc::new(config))
+            .connect(ServerName::try_from("localhost").unwrap(), stream)
+            .await?;
+
+        // TLS 1.3 clients finish before the server has checked their
+        // certificate, so round trip some data to see the verdict.
+        stream.write_all(b"ping").await?;
+        stream.flush().await?;
+        Ok(stream)
+    }
+
+    async fn accept(incoming: &mut TlsIncoming) -> TlsConnection {
+        poll_fn(|cx| Pin::new(&mut *incoming).poll_accept(cx))
+            .await
+            .unwrap()
+            .unwrap()
+    }
+
+    /// Connects as `client` and checks the server refused the handshake with
+    /// `alert` and counted it under `reason`.
+    async fn assert_refused(
+        addr: SocketAddr,
+        client: Option<&str>,
+        alert: AlertDescription,
+        reason: &str,
+    ) {
+        let before = metrics::tls_handshake_failures(reason);
+        let err = match connect(addr, client).await {
+            Ok(mut stream) => stream.read(&mut [0u8; 4]).await.unwrap_err(),
+            Err(e) => e,
+        };
+        let received = err.get_ref().and_then(|e| e.downcast_ref::<TlsError>());
+        assert_eq!(received, Some(&TlsError::AlertReceived(alert)), "{}", err);
+
+        // The server counts the failure after it has sent the alert.
+        for _ in 0..100 {
+            if metrics::tls_handshake_failures(reason) > before {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("handshake failure {} was not counted", reason);
+    }
+
+    #[test]
+    fn test_load_key_pair() {
+        let key_pair = load_key_pair(&cert_path("tls.crt"), &cert_path("tls.key")).unwrap();
+        assert_eq!(key_pair.certs.len(), 1);
+        assert!(key_pair.expiry > 4_000_000_000);
+
+        let err = load_key_pair(&cert_path("tls.key"), &cert_path("tls.key"))
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("No certificates found"));
+    }
+
+    #[test]
+    fn test_load_combined_bundle() {
+        let key_pair = load_key_pair(&cert_path("chain.pem"), &cert_path("chain.pem")).unwrap();
+        assert_eq!(key_pair.certs.len(), 2);
+        assert!(matches!(key_pair.key, PrivateKeyDer::Pkcs1(_)));
+    }
+
+    #[test]
+    fn test_rejects_mismatched_key() {
+        let err = load_key_pair(&cert_path("tls.crt"), &cert_path("apiserver.key"))
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("does not work with certificate"));
+    }
+
+    #[test]
+    fn test_rejects_chain_starting_with_ca() {
+        let err = load_key_pair(&cert_path("ca.crt"), &cert_path("tls.key"))
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("is a CA certificate"));
+    }
+
+    #[test]
+    fn test_parse_settings() {
+        assert_eq!(
+            parse_min_version("1.3").unwrap().version,
+            ProtocolVersion::TLSv1_3
+        );
+        assert!(parse_min_version("1.1").is_err());
+
+        let suites = parse_cipher_suites(&["TLS13_AES_256_GCM_SHA384"]).unwrap();
+        assert_eq!(suites.len(), 1);
+        let err = parse_cipher_suites(&["TLS_RSA_WITH_RC4_128_SHA"])
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("TLS13_AES_128_GCM_SHA256"));
+
+        assert_eq!(parse_alpn_protocols(&["h2"]).unwrap(), vec![b"h2".to_vec()]);
+        assert!(parse_alpn_protocols(&["spdy/3"]).is_err());
+    }
+
+    #[test]
+    fn test_no_cipher_suites_for_min_version() {
+        let settings = TlsSettings {
+            min_version: &TLS13,
+            cipher_suites: parse_cipher_suites(&["TLS_ECDHE_ECDSA_WITH_AES_128_GCM_SHA256"])
+                .unwrap(),
+            ..Default::default()
+        };
+        let cert = Arc::new(serving_cert("tls.crt", "tls.key"));
+        assert!(server_config(cert, &settings, None).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_serves_intermediate_chain() {
+        let mut incoming =
+            serve_with("chain.pem", "chain.pem", &TlsSettings::default(), None).await;
+        let _client = connect_with(incoming.local_addr(), "chain-ca.crt", &[&TLS13], None)
+            .await
+            .unwrap();
+        accept(&mut incoming).await;
+    }
+
+    #[tokio::test]
+    async fn test_replace_serving_cert() {
+        let cert = Arc::new(serving_cert("tls.crt", "tls.key"));
+        let config = server_config(cert.clone(), &TlsSettings::default(), None).unwrap();
+        let mut incoming = TlsIncoming::bind("127.0.0.1:0".parse().unwrap(), config)
+            .await
+            .unwrap();
+        let addr = incoming.local_addr();
+
+        let _client = connect(addr, None).await.unwrap();
+        accept(&mut incoming).await;
+
+        cert.replace(load_key_pair(&cert_path("chain.pem"), &cert_path("chain.pem")).unwrap())
+            .unwrap();
+        assert!(connect(addr, None).await.is_err());
+        let _client = connect_with(addr, "chain-ca.crt", &[&TLS13], None)
+            .await
+            .unwrap();
+        accept(&mut incoming).await;
+    }
+
+    #[tokio::test]
+    async fn test_tls13_only() {
+        let settings = TlsSettings {
+            min_version: &TLS13,
+            ..Default::default()
+        };
+        let mut incoming = serve_with("tls.crt", "tls.key", &settings, None).await;
+
+        assert!(
+            connect_with(incoming.local_addr(), "ca.crt", &[&TLS12], None)
+                .await
+                .is_err()
+        );
+
+        let client = connect_with(incoming.local_addr(), "ca.crt", &[&TLS13], None)
+            .await
+            .unwrap();
+        let (_, connection) = client.get_ref();
+        assert_eq!(
+            connection.protocol_version(),
+            Some(ProtocolVersion::TLSv1_3)
+        );
+        accept(&mut incoming).await;
+    }
+
+    #[tokio::test]
+    async fn test_without_client_auth() {
+        let mut incoming = serve(None).await;
+        let client = connect(incoming.local_addr(), None).await.unwrap();
+
+        let mut server = accept(&mut incoming).await;
+        let mut buf = [0u8; 4];
+        server.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"ping");
+        drop(client);
+    }
+
+    #[tokio::test]
+    async fn test_allowed_client() {
+        let mut incoming = serve(api_server_only()).await;
+        let mut client = connect(incoming.local_addr(), Some("apiserver"))
+            .await
+            .unwrap();
+
+        let mut server = accept(&mut incoming).await;
+        let mut buf = [0u8; 4];
+        server.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"ping");
+
+        server.write_all(b"pong").await.unwrap();
+        client.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"pong");
+    }
+
+    #[tokio::test]
+    async fn test_rejects_client_not_allowed() {
+        let incoming = serve(api_server_only()).await;
+        assert_refused(
+            incoming.local_addr(),
+            Some("intruder"),
+            AlertDescription::AccessDenied,
+            "client_not_allowed",
+        )
+        .await;
+    }
+
+    #[tokio::test]
+    async fn test_rejects_missing_client_certificate() {
+        let incoming = serve(api_server_only()).await;
+        assert_refused(
+            incoming.local_addr(),
+            None,
+            AlertDescription::CertificateRequired,
+            "client_certificate_missing",
+        )
+        .await;
+    }
+
+    #[tokio::test]
+    async fn test_any_client_signed_by_ca() {
+        let mut incoming = serve(Some(ClientAuth {
+            ca_path: cert_path("ca.crt"),
+            allowed_names: Vec::new(),
+        }))
+        .await;
+        let _client = connect(incoming.local_addr(), Some("intruder"))
+            .await
+            .unwrap();
+        accept(&mut incoming).await;
+    }
+
+    #[test]
+    fn test_handshake_failure_reason() {
+        let err = |e: TlsError| io::Error::new(io::ErrorKind::InvalidData, e);
+        assert_eq!(
+            handshake_failure_reason(&err(TlsError::NoCertificatesPresented)),
+            "client_certificate_missing"
+        );
+        assert_eq!(
+            handshake_failure_reason(&err(TlsError::InvalidCertificate(
+                CertificateError::ApplicationVerificationFailure
+            ))),
+            "client_not_allowed"
+        );
+        assert_eq!(
+            handshake_failure_reason(&err(TlsError::InvalidCertificate(
+                CertificateError::UnknownIssuer
+            ))),
+            "client_certificate_invalid"
+        );
+        assert_eq!(
+            handshake_failure_reason(&io::Error::from(io::ErrorKind::UnexpectedEof)),
+            "handshake_error"
+        );
+    }
+}
@@ -0,0 +1,163 @@
+use anyhow::{anyhow, Result};
+use std::{
+    fs,
+    future::Future,
+    io,
+    os::unix::fs::{FileTypeExt, PermissionsExt},
+    path::{Path, PathBuf},
+    pin::Pin,
+    task::{ready, Context, Poll},
+};
+use tokio::{
+    net::{UnixListener, UnixStream},
+    time::Sleep,
+};
+use warp::hyper::server::accept::Accept;
+
+use super::accept::AcceptBackoff;
+
+/// Plain HTTP connections on a Unix domain socket, for test harnesses and
+/// sidecar proxies that terminate TLS themselves. The socket file is removed
+/// when the listener is dropped.
+pub struct UnixIncoming {
+    path: PathBuf,
+    listener: UnixListener,
+    backoff: AcceptBackoff,
+    /// Set while waiting to retry after a failed accept.
+    retry: Option<Pin<Box<Sleep>>>,
+}
+
+impl UnixIncoming {
+    /// Binds `path` with permissions `mode`, replacing a socket left behind by
+    /// an earlier run. Any other file there is an error.
+    pub fn bind(path: &Path, mode: u32) -> Result<UnixIncoming> {
+        match fs::symlink_metadata(path) {
+            Ok(meta) if meta.file_type().is_socket() => fs::remove_file(path)
+                .map_err(|e| anyhow!("Error removing stale socket {}: {}", path.display(), e))?,
+            Ok(_) => return Err(anyhow!("{} exists and is not a socket", path.display())),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => {
+                return Err(anyhow!(
+                    "Error reading metadata for {}: {}",
+                    path.display(),
+                    e
+                ))
+            }
+        }
+
+        let listener = UnixListener::bind(path)
+            .map_err(|e| anyhow!("Error binding listener to {}: {}", path.display(), e))?;
+        // Anyone who can connect can post AdmissionReviews, so don't leave it
+        // to the umask.
+        fs::set_permissions(path, fs::Permissions::from_mode(mode))
+            .map_err(|e| anyhow!("Error setting permissions on {}: {}", path.display(), e))?;
+        Ok(UnixIncoming {
+            path: path.to_path_buf(),
+            listener,
+            backoff: AcceptBackoff::new(),
+            retry: None,
+        })
+    }
+}
+
+impl Accept for UnixIncoming {
+    type Conn = UnixStream;
+    type Error = io::Error;
+
+    fn poll_accept(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Self::Conn, Self::Error>>> {
+        loop {
+            if let Some(retry) = &mut self.retry {
+                ready!(retry.as_mut().poll(cx));
+                self.retry = None;
+            }
+
+            match ready!(self.listener.poll_accept(cx)) {
+                Ok((stream, _)) => {
+                    self.backoff.reset();
+                    return Poll::Ready(Some(Ok(stream)));
+                }
+                Err(e) => {
+                    let delay = self.backoff.failed(&e);
+                    self.retry = Some(Box::pin(tokio::time::sleep(delay)));
+                }
+            }
+        }
+    }
+}
+
+impl Drop for UnixIncoming {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::{
+        admission::AdmissionReviewResponse, killswitch::KillSwitch, limits::Limits, policy::Policy,
+        policy::SharedPolicy, readyz::Readiness, recent::RecentDecisions, redact::Redactor, routes,
+        testing::TempDir,
+    };
+    use std::sync::Arc;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use warp::hyper::{service::make_service_fn, Server};
+
+    #[tokio::test]
+    async fn test_serve_unix_socket() {
+        let dir = TempDir::new("unix");
+        let path = dir.join("webhook.sock");
+        // A socket left behind by an earlier run is replaced.
+        drop(std::os::unix::net::UnixListener::bind(&path).unwrap());
+
+        let incoming = UnixIncoming::bind(&path, 0o600).unwrap();
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        let service = warp::service(routes(
+            Arc::new(SharedPolicy::new(None, Policy::default())),
+            Arc::new(Redactor::default()),
+            None,
+            Arc::new(RecentDecisions::new(0)),
+            Arc::new(KillSwitch::new(false, None, Arc::new(Readiness::new()))),
+            Arc::new(Limits::default()),
+        ));
+        let server = Server::builder(incoming).serve(make_service_fn(move |_| {
+            let service = service.clone();
+            async move { Ok::<_, std::convert::Infallible>(service) }
+        }));
+        let server = tokio::spawn(server);
+
+        let review = include_str!("../../data/admission.json");
+        let mut stream = UnixStream::connect(&path).await.unwrap();
+        stream
+            .write_all(
+                format!(
+                    "POST /mutate HTTP/1.1\r\nhost: localhost\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+                    review.len(),
+                    review
+                )
+                .as_bytes(),
+            )
+            .await
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        let (_, body) = response.split_once("\r\n\r\n").unwrap();
+        let result: AdmissionReviewResponse = serde_json::from_str(body).unwrap();
+        let resp = result.response.expect("response missing");
+        assert_eq!(resp.uid, "0cc44252-2984-4e9d-8395-bcdd4efaa329");
+        assert!(resp.allowed);
+        assert_eq!(resp.patch_type, Some("JSONPatch".to_string()));
+
+        server.abort();
+        let _ = server.await;
+        assert!(!path.exists());
+
+        fs::write(&path, "").unwrap();
+        assert!(UnixIncoming::bind(&path, 0o600).is_err());
+    }
+}
@@ -0,0 +1,109 @@
+use log::debug;
+use serde::Serialize;
+use std::{convert::Infallible, sync::Arc};
+use warp::Filter;
+
+use super::metrics;
+use super::policy::SharedPolicy;
+
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+// Set by build.rs.
+pub const GIT_COMMIT: &str = env!("PROTECT_WEBHOOK_GIT_COMMIT");
+pub const BUILD_TIME: &str = env!("PROTECT_WEBHOOK_BUILD_TIME");
+const FEATURES: &str = env!("PROTECT_WEBHOOK_FEATURES");
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct VersionInfo {
+    version: &'static str,
+    git_commit: &'static str,
+    build_time: &'static str,
+    features: Vec<&'static str>,
+    policy_hash: String,
+    /// Whether `/mutate` is served over plain HTTP for development.
+    insecure_http: bool,
+}
+
+/// The Cargo features the binary was built with.
+pub fn features() -> Vec<&'static str> {
+    FEATURES
+        .split(',')
+        .filter(|feature| !feature.is_empty())
+        .collect()
+}
+
+/// Publishes the build and the active policy as `protect_webhook_build_info`.
+/// Called again whenever the policy changes.
+pub fn publish(policy_hash: &str) {
+    metrics::set_build_info(
+        VERSION,
+        GIT_COMMIT,
+        BUILD_TIME,
+        &features().join(","),
+        policy_hash,
+    );
+}
+
+pub fn handler(
+    policy: Arc<SharedPolicy>,
+    insecure_http: bool,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::get()
+        .and(warp::path("version"))
+        .and(with_policy(policy))
+        .map(move |policy: Arc<SharedPolicy>| {
+            debug!("GET /version");
+            warp::reply::json(&VersionInfo {
+                version: VERSION,
+                git_commit: GIT_COMMIT,
+                build_time: BUILD_TIME,
+                features: features(),
+                policy_hash: policy.current().hash(),
+                insecure_http,
+            })
+        })
+}
+
+fn with_policy(
+    policy: Arc<SharedPolicy>,
+) -> impl Filter<Extract = (Arc<SharedPolicy>,), Error = Infallible> + Clone {
+    warp::any().map(move || policy.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::policy::Policy;
+    use serde_json::Value;
+    use warp::test::request;
+
+    #[tokio::test]
+    async fn test_version() {
+        let policy = Policy::default();
+        let hash = policy.hash();
+
+        let response = request()
+            .path("/version")
+            .reply(&handler(Arc::new(SharedPolicy::new(None, policy)), false))
+            .await;
+
+        assert_eq!(response.status(), 200);
+        let body: Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(body["version"], env!("CARGO_PKG_VERSION"));
+        assert_eq!(body["policyHash"], hash);
+        assert!(body["features"].is_array());
+        assert!(humantime::parse_rfc3339(body["buildTime"].as_str().unwrap()).is_ok());
+        assert!(!body["gitCommit"].as_str().unwrap().is_empty());
+        assert_eq!(body["insecureHttp"], false);
+
+        let response = request()
+            .path("/version")
+            .reply(&handler(
+                Arc::new(SharedPolicy::new(None, Policy::default())),
+                true,
+            ))
+            .await;
+        let body: Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(body["insecureHttp"], true);
+    }
+}